@@ -0,0 +1,64 @@
+//Run with `cargo bench --bench lexer`, reports the best of several runs over a large script
+//alongside the old tokenize + lexer pipeline it replaced
+use lemurlib::lexer::Lexer;
+use lemurlib::parser::Parser;
+use std::time::Instant;
+
+mod old_lexer;
+
+const CHUNK: &str = r#"
+x1 = 10.5 * (y + 2) ^ 2
+name = "lemur $x1 and $(x1 + 1)"
+if x1 > 5 then
+    println(format("{:>8.2f}", x1), [1, 2, 3])
+elseif x1 < 2 then
+    z = sqrt(x1) #= block =#
+else
+    s = "escaped \"quote\"" # trailing
+end
+"#;
+
+const RUNS: usize = 10;
+
+fn best_of<F: FnMut() -> usize>(mut f: F) -> (usize, f64) {
+    let mut best = f64::MAX;
+    let mut count = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        count = f();
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    (count, best * 1000.0)
+}
+
+fn main() {
+    let script = CHUNK.repeat(5000);
+
+    let (tokens, ms) = best_of(|| {
+        let mut count = 0;
+        for token in Lexer::new(&script) {
+            token.expect("The benchmark script lexes");
+            count += 1;
+        }
+        count
+    });
+    println!("lexer: {} tokens in {:.2}ms", tokens, ms);
+
+    //The pipeline the streaming lexer replaced, tokenizing everything up front then converting
+    let (tokens, ms) = best_of(|| {
+        let strings = old_lexer::tokenize(&script).expect("The benchmark script tokenizes");
+        let mut lexer = old_lexer::Lexer::new(strings);
+        let mut count = 0;
+        while lexer.next() != old_lexer::Token::EOF {
+            count += 1;
+        }
+        count
+    });
+    println!("old tokenize + lexer: {} tokens in {:.2}ms", tokens, ms);
+
+    let (statements, ms) = best_of(|| match Parser::new(Lexer::new(&script)).parse_block() {
+        Ok(lemurlib::atom::AST::Node(block)) => block.tail.len(),
+        res => panic!("Expected the benchmark script to parse but got {:?}", res),
+    });
+    println!("lexer and parser: {} statements in {:.2}ms", statements, ms);
+}
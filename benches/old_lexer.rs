@@ -0,0 +1,476 @@
+//A frozen copy of the tokenize + Lexer pipeline that the streaming lexer replaced, kept so the
+//benchmark can compare the two on the same script. Only what's needed to turn source into tokens
+//is kept, don't use it for anything else.
+#![allow(dead_code, clippy::upper_case_acronyms, clippy::enum_variant_names)]
+
+use std::error;
+use std::fmt;
+use unicode_xid::UnicodeXID;
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    UnknownChar(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidInterpolation,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownChar(c) => write!(f, "Unknown character {:?}", c),
+            Error::UnterminatedString => write!(f, "Unterminated string literal"),
+            Error::UnterminatedComment => write!(f, "Unterminated #= block comment"),
+            Error::InvalidInterpolation => {
+                write!(f, "Expected a name or bracket after $ in a string")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+//Byte offsets into the source, end is exclusive
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+//A comment skipped over by the tokenizer, the text includes the # delimiters
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    pub text: String,
+    pub span: Span,
+}
+
+pub fn tokenize(s: &str) -> Result<Vec<String>> {
+    tokenize_with_trivia(s).map(|(tokens, _)| tokens)
+}
+
+//Like tokenize but also returns the comments, so tools such as a formatter can re-emit them
+pub fn tokenize_with_trivia(s: &str) -> Result<(Vec<String>, Vec<Trivia>)> {
+    let mut tokenizer = Tokenizer {
+        src: s,
+        idx: 0,
+        tokens: vec![],
+        trivia: vec![],
+    };
+    tokenizer.tokenize_until(false)?;
+    Ok((tokenizer.tokens, tokenizer.trivia))
+}
+
+//Marks the start and end of an interpolated string. Between them the literal parts are
+//quoted strings and each interpolated expression is wrapped in brackets, so "a $x" is
+//split into $" "a " ( x ) "$
+pub const INTERPOLATION_START: &str = "$\"";
+pub const INTERPOLATION_END: &str = "\"$";
+
+struct Tokenizer<'a> {
+    src: &'a str,
+    //Byte offset of the next character, always on a character boundary
+    idx: usize,
+    tokens: Vec<String>,
+    trivia: Vec<Trivia>,
+}
+
+impl<'a> Tokenizer<'a> {
+    //Tokenizes to the end of the input, or when nested inside an interpolation up to and
+    //including the bracket that closes it. Newlines are kept as tokens since they end
+    //statements, except inside brackets where an expression can span several lines.
+    fn tokenize_until(&mut self, nested: bool) -> Result<()> {
+        let mut depth = 0;
+
+        while let Some(c) = self.peek(0) {
+            match c {
+                ')' if nested && depth == 0 => {
+                    self.push_while(|_, i| i == 0);
+                    return Ok(());
+                }
+                '#' => self.skip_comment()?,
+                c if "<>[]{}(),:;+-*/&|!%$@=^".contains(c) => {
+                    match c {
+                        '(' | '[' | '{' => depth += 1,
+                        ')' | ']' | '}' => depth -= 1,
+                        _ => (),
+                    }
+                    match (c, self.peek(1)) {
+                        ('!', Some('=')) | ('=', Some('=')) => self.push_while(|_, i| i < 2),
+                        _ => self.push_while(|_, i| i == 0),
+                    }
+                }
+                '"' => self.tokenize_string()?,
+                c if c.is_ascii_digit() => {
+                    let start = self.idx;
+                    self.skip_digits();
+                    //Only treat a dot as a decimal point when a digit follows it
+                    if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit())
+                    {
+                        self.idx += 1;
+                        self.skip_digits();
+                    }
+                    if let Some('e') | Some('E') = self.peek(0) {
+                        let sign = match self.peek(1) {
+                            Some('+') | Some('-') => 1,
+                            _ => 0,
+                        };
+                        if self.peek(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                            self.idx += 1 + sign;
+                            self.skip_digits();
+                        }
+                    }
+                    self.tokens.push(self.src[start..self.idx].to_string());
+                }
+                c if is_symbol_start(c) => {
+                    let start = self.idx;
+                    self.skip_symbol();
+                    self.tokens.push(self.src[start..self.idx].to_string());
+                }
+                '\n' if !nested && depth == 0 => {
+                    //Blank lines and leading newlines carry no meaning so are collapsed
+                    if self.tokens.last().is_some_and(|token| token != "\n") {
+                        self.tokens.push("\n".to_string());
+                    }
+                    self.idx += 1;
+                }
+                c if c.is_whitespace() => {
+                    self.idx += c.len_utf8();
+                }
+                c => return Err(Error::UnknownChar(c)),
+            }
+        }
+
+        if nested {
+            return Err(Error::UnterminatedString);
+        }
+        Ok(())
+    }
+
+    //Skips a # line comment or a #= =# block comment, block comments can be nested
+    fn skip_comment(&mut self) -> Result<()> {
+        let start = self.idx;
+
+        if self.peek(1) == Some('=') {
+            let mut depth = 0;
+            loop {
+                match (self.peek(0), self.peek(1)) {
+                    (Some('#'), Some('=')) => {
+                        depth += 1;
+                        self.idx += 2;
+                    }
+                    (Some('='), Some('#')) => {
+                        depth -= 1;
+                        self.idx += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    (Some(c), _) => self.idx += c.len_utf8(),
+                    (None, _) => return Err(Error::UnterminatedComment),
+                }
+            }
+        } else {
+            while let Some(c) = self.peek(0) {
+                if c == '\n' {
+                    break;
+                }
+                self.idx += c.len_utf8();
+            }
+        }
+
+        let text = self.src[start..self.idx].to_string();
+        let span = Span {
+            start,
+            end: self.idx,
+        };
+        self.trivia.push(Trivia { text, span });
+        Ok(())
+    }
+
+    //Pushes a plain string as a single token, otherwise splits out the interpolated parts
+    fn tokenize_string(&mut self) -> Result<()> {
+        let first = self.tokens.len();
+        let quote = self.idx;
+        self.idx += 1;
+        let mut start = self.idx;
+
+        loop {
+            match self.peek(0) {
+                None => return Err(Error::UnterminatedString),
+                Some('"') => break,
+                //Skip over the escaped character so \" doesn't end the string
+                Some('\\') => {
+                    self.idx += 1;
+                    if let Some(c) = self.peek(0) {
+                        self.idx += c.len_utf8();
+                    }
+                }
+                Some('$') => {
+                    if self.tokens.len() == first {
+                        self.tokens.push(INTERPOLATION_START.to_string());
+                    }
+                    self.push_literal(start, self.idx);
+
+                    self.idx += 1;
+                    match self.peek(0) {
+                        Some('(') => {
+                            self.push_while(|_, i| i == 0);
+                            self.tokenize_until(true)?;
+                        }
+                        Some(c) if is_symbol_start(c) => {
+                            let symbol = self.idx;
+                            self.skip_symbol();
+                            self.tokens.push("(".to_string());
+                            self.tokens.push(self.src[symbol..self.idx].to_string());
+                            self.tokens.push(")".to_string());
+                        }
+                        _ => return Err(Error::InvalidInterpolation),
+                    }
+                    start = self.idx;
+                }
+                Some(c) => self.idx += c.len_utf8(),
+            }
+        }
+
+        if self.tokens.len() == first {
+            self.idx += 1;
+            self.tokens.push(self.src[quote..self.idx].to_string());
+        } else {
+            self.push_literal(start, self.idx);
+            self.idx += 1;
+            self.tokens.push(INTERPOLATION_END.to_string());
+        }
+        Ok(())
+    }
+
+    //Pushes the text between start and end as a quoted string, unless it's empty
+    fn push_literal(&mut self, start: usize, end: usize) {
+        if end > start {
+            self.tokens.push(format!("\"{}\"", &self.src[start..end]));
+        }
+    }
+
+    //Pushes a token made of the characters accepted by f, which is given each character
+    //along with its position in the token
+    fn push_while(&mut self, f: impl Fn(char, usize) -> bool) {
+        let start = self.idx;
+        let mut n = 0;
+        while let Some(c) = self.peek(0) {
+            if !f(c, n) {
+                break;
+            }
+            self.idx += c.len_utf8();
+            n += 1;
+        }
+        self.tokens.push(self.src[start..self.idx].to_string());
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.src[self.idx..].chars().nth(n)
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            self.idx += 1;
+        }
+    }
+
+    //Skips the rest of a symbol, a ! is only part of the symbol when it isn't the start of !=
+    fn skip_symbol(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if !is_symbol_char(c) || (c == '!' && self.peek(1) == Some('=')) {
+                break;
+            }
+            self.idx += c.len_utf8();
+        }
+    }
+}
+
+//Like Julia's identifiers, symbols start with a letter or underscore and continue with
+//letters, digits, underscores, exclamation marks and sub or superscript digits
+pub fn is_symbol_start(c: char) -> bool {
+    c == '_' || UnicodeXID::is_xid_start(c)
+}
+
+pub fn is_symbol_char(c: char) -> bool {
+    c == '!'
+        || UnicodeXID::is_xid_continue(c)
+        || ('\u{2070}'..='\u{209f}').contains(&c)
+        || c == '\u{b2}'
+        || c == '\u{b3}'
+        || c == '\u{b9}'
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Symbol(String),
+    String(String),
+    //Interpolated strings
+    StringStart,
+    StringEnd,
+    //Numbers
+    Float(f64),
+    //Brackets
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    //Mathematical Operators
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Power,
+    //Logical Operators
+    True,
+    False,
+    Equal,
+    NotEqual,
+    Not,
+    Then,
+    GreaterThan,
+    LessThan,
+    //Keywords,
+    If,
+    Else,
+    ElseIf,
+    End,
+    //Statement terminators
+    NewLine,
+    Semicolon,
+
+    //Assignment
+    Assign,
+    //Unknown things
+    EOF,
+    UnknownToken(String),
+}
+
+pub struct Lexer {
+    tokens: Vec<String>,
+    idx: usize,
+}
+
+impl Lexer {
+    pub fn new(tokens: Vec<String>) -> Self {
+        Lexer { tokens, idx: 0 }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Token {
+        let token = self.peek(0);
+        self.idx += 1;
+        token
+    }
+
+    pub fn peek(&mut self, n: usize) -> Token {
+        if self.idx + n < self.tokens.len() {
+            str_to_token(&self.tokens[self.idx + n])
+        } else {
+            Token::EOF
+        }
+    }
+
+    pub fn all(&mut self) -> Vec<Token> {
+        let mut tokens = vec![];
+        while self.peek(0) != Token::EOF {
+            tokens.push(self.next())
+        }
+        self.idx = 0;
+        tokens
+    }
+}
+
+fn str_to_token(token: &str) -> Token {
+    match token {
+        //Mathematical operators
+        "+" => Token::Plus,
+        "-" => Token::Minus,
+        "/" => Token::Divide,
+        "*" => Token::Multiply,
+        "^" => Token::Power,
+        //Logical Operators
+        "!" => Token::Not,
+        "<" => Token::LessThan,
+        ">" => Token::GreaterThan,
+        //Assignemnt
+        "=" => Token::Assign,
+        "!=" => Token::NotEqual,
+        "==" => Token::Equal,
+        //Brackets
+        "(" => Token::LParen,
+        ")" => Token::RParen,
+        "[" => Token::LBracket,
+        "]" => Token::RBracket,
+        "," => Token::Comma,
+        //Keywords
+        "if" => Token::If,
+        "then" => Token::Then,
+        "else" => Token::Else,
+        "elseif" => Token::ElseIf,
+        "end" => Token::End,
+        "\n" => Token::NewLine,
+        ";" => Token::Semicolon,
+        "false" => Token::False,
+        "true" => Token::True,
+
+        //Literals
+        INTERPOLATION_START => Token::StringStart,
+        INTERPOLATION_END => Token::StringEnd,
+        token if token.starts_with('"') => match unescape(&token[1..token.len() - 1]) {
+            Some(value) => Token::String(value),
+            None => Token::UnknownToken(token.to_string()),
+        },
+        token if is_f64(token) => Token::Float(token.parse::<f64>().unwrap()),
+        token if is_valid_symbol(token) => Token::Symbol(token.to_string()),
+        token => Token::UnknownToken(token.to_string()),
+    }
+}
+
+//Rust also parses words like inf and NaN as floats, those are left to be symbols
+fn is_f64(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit()) && s.parse::<f64>().is_ok()
+}
+
+//Resolves the escape sequences of a string literal, returning None for an invalid escape
+fn unescape(s: &str) -> Option<String> {
+    let mut value = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '$' => '$',
+            '\\' => '\\',
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+            }
+            _ => return None,
+        };
+        value.push(escaped);
+    }
+    Some(value)
+}
+
+fn is_valid_symbol(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(is_symbol_start) && chars.all(is_symbol_char)
+}
@@ -1,5 +1,15 @@
-#[derive(PartialEq, Debug)]
-pub struct Symbol(pub String);
+use crate::lexer::Span;
+use crate::symbol::Symbol;
+use std::rc::Rc;
+
+const STACK_RED_ZONE: usize = 64 * 1024;
+const STACK_SEGMENT: usize = 1024 * 1024;
+
+//The parser, each pass over the tree and the tree walker recurse once per level of
+//nesting, so they call this to grow the stack onto the heap when it runs low
+pub fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, f)
+}
 
 pub enum Keyword {
     If,
@@ -10,7 +20,7 @@ pub enum Keyword {
 #[derive(PartialEq, Debug)]
 pub enum Atom {
     //Literals
-    Symbol(String),
+    Symbol(Symbol),
     String(String),
     Float(f64),
     True,
@@ -33,14 +43,49 @@ pub enum Atom {
     //Semanitcs
     Assign,
     Block,
+    Call,
+    Array,
+    Index,
+    Interpolate,
+    While,
+    //try body catch e handler end is Try[body, e or nothing, handler], a finally block
+    //wraps it in Finally[try, cleanup]
+    Try,
+    Finally,
+    Return,
+    //p.x is Field(x)[p]
+    Field(Symbol),
+    Struct(Rc<Struct>),
+    //A variable inside a function body, resolved to a slot in a call frame
+    Local(Local),
+    Function(Rc<Function>),
     Nothing,
     End,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub struct Node {
     pub head: Atom,
     pub tail: Vec<AST>,
+    //Where the expression is in the source, empty for nodes made by the optimiser
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(head: Atom, tail: Vec<AST>) -> Self {
+        Node {
+            head,
+            tail,
+            span: Span::default(),
+        }
+    }
+}
+
+//Trees are compared by their structure, wherever they came from in the source
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.head == other.head && self.tail == other.tail
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -48,3 +93,47 @@ pub enum AST {
     Node(Node),
     Atom(Atom),
 }
+
+//A local variable found `depth` frames up from the current one, 0 being the current frame
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Local {
+    pub name: Symbol,
+    pub depth: usize,
+    pub slot: usize,
+}
+
+//The parameters take the first slots of the frame, followed by the other locals
+#[derive(PartialEq, Debug)]
+pub struct Function {
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    //The name of the type each parameter is annotated with, looked up as a global when
+    //the function is called
+    pub types: Vec<Option<Symbol>>,
+    pub slots: usize,
+    pub body: AST,
+}
+
+impl Function {
+    //How the function was declared, such as f(x::Float, y)
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .zip(&self.types)
+            .map(|(param, ty)| match ty {
+                Some(ty) => format!("{}::{}", param, ty),
+                None => param.to_string(),
+            })
+            .collect();
+        format!("{}({})", self.name, params.join(", "))
+    }
+}
+
+//A type defined by a struct definition, its constructor takes the fields in order
+#[derive(PartialEq, Debug)]
+pub struct Struct {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+    pub mutable: bool,
+}
@@ -0,0 +1,212 @@
+use crate::interpreter::{Error, Result};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+//What a script may do outside of computing, consulted by every builtin that touches the
+//file system, environment, other processes or the clock. The default allows nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    //Directories and files that can be read, including everything below them
+    pub read: Vec<PathBuf>,
+    //Directories and files that can be written, created or removed
+    pub write: Vec<PathBuf>,
+    pub env: bool,
+    pub exec: bool,
+    pub clock: bool,
+}
+
+impl Capabilities {
+    //Scripts can only compute, the same as the default
+    pub fn pure() -> Self {
+        Capabilities::default()
+    }
+
+    //Everything is allowed, for trusted scripts
+    pub fn all() -> Self {
+        Capabilities {
+            read: vec![PathBuf::from("/")],
+            write: vec![PathBuf::from("/")],
+            env: true,
+            exec: true,
+            clock: true,
+        }
+    }
+
+    //Returns the absolute path to read from if it's inside an allowed path
+    pub fn check_read(&self, path: &Path) -> Result<PathBuf> {
+        check_path(&self.read, path, "read")
+    }
+
+    pub fn check_write(&self, path: &Path) -> Result<PathBuf> {
+        check_path(&self.write, path, "write")
+    }
+
+    //Like check_write but the last part of the path isn't followed if it's a symlink, so
+    //removing a link inside an allowed directory removes the link and not what it points to
+    pub fn check_remove(&self, path: &Path) -> Result<PathBuf> {
+        let path = clean(path);
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => resolve(parent.to_path_buf()).join(name),
+            _ => path,
+        };
+        check_normalised(&self.write, path, "write")
+    }
+
+    pub fn check_env(&self) -> Result<()> {
+        check(self.env, "access the environment")
+    }
+
+    pub fn check_exec(&self) -> Result<()> {
+        check(self.exec, "run processes")
+    }
+
+    pub fn check_clock(&self) -> Result<()> {
+        check(self.clock, "read the clock")
+    }
+}
+
+fn check(allowed: bool, action: &str) -> Result<()> {
+    match allowed {
+        true => Ok(()),
+        false => Err(Error::PermissionDenied(format!(
+            "the script is not allowed to {}",
+            action
+        ))),
+    }
+}
+
+fn check_path(allowed: &[PathBuf], path: &Path, action: &str) -> Result<PathBuf> {
+    check_normalised(allowed, normalise(path), action)
+}
+
+fn check_normalised(allowed: &[PathBuf], path: PathBuf, action: &str) -> Result<PathBuf> {
+    if allowed.iter().any(|root| path.starts_with(normalise(root))) {
+        return Ok(path);
+    }
+    Err(Error::PermissionDenied(format!(
+        "the script is not allowed to {} {}",
+        action,
+        path.display()
+    )))
+}
+
+//Makes the path absolute and removes any `.` or `..` so it can't escape an allowed
+//directory. The part of it that exists is canonicalized so symlinks are followed too.
+fn normalise(path: &Path) -> PathBuf {
+    resolve(clean(path))
+}
+
+fn clean(path: &Path) -> PathBuf {
+    let path = match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_path_buf(),
+    };
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                clean.pop();
+            }
+            component => clean.push(component),
+        }
+    }
+    clean
+}
+
+fn resolve(clean: PathBuf) -> PathBuf {
+    let mut existing = clean.as_path();
+    let mut rest = vec![];
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return clean,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enviroment::Enviroment;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+    use std::fs;
+
+    #[test]
+    fn pure_by_default() {
+        let capabilities = Capabilities::default();
+        assert_eq!(capabilities, Capabilities::pure());
+        assert!(capabilities.check_read(Path::new("/")).is_err());
+        assert!(capabilities.check_write(Path::new("file.txt")).is_err());
+        assert!(capabilities.check_env().is_err());
+        assert!(capabilities.check_exec().is_err());
+        assert_eq!(
+            capabilities.check_clock(),
+            Err(Error::PermissionDenied(
+                "the script is not allowed to read the clock".to_string()
+            ))
+        );
+
+        let capabilities = Capabilities::all();
+        assert!(capabilities.check_read(Path::new("/etc/hosts")).is_ok());
+        assert!(capabilities.check_exec().is_ok());
+    }
+
+    #[test]
+    fn paths_stay_inside_allowed_directories() {
+        let root = env::temp_dir().join(format!("lemur_capabilities_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("data")).unwrap();
+        let capabilities = Capabilities {
+            read: vec![root.clone()],
+            write: vec![root.join("data")],
+            ..Capabilities::pure()
+        };
+        let root = root.canonicalize().unwrap();
+
+        assert_eq!(
+            capabilities.check_read(&root.join("data/./new.txt")),
+            Ok(root.join("data/new.txt"))
+        );
+        assert!(capabilities.check_write(&root.join("data/new.txt")).is_ok());
+        assert!(capabilities.check_write(&root.join("other.txt")).is_err());
+        assert!(capabilities.check_read(&root.join("../other.txt")).is_err());
+        assert!(capabilities
+            .check_write(&root.join("data/../../data/x"))
+            .is_err());
+
+        //A prefix of the name isn't enough, the path has to be inside the directory
+        let sibling = PathBuf::from(format!("{}_sibling", root.display()));
+        assert!(capabilities.check_read(&sibling).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn builtins_check_capabilities() {
+        let ast = Parser::new(Lexer::new("time() > 0"))
+            .parse_program()
+            .unwrap();
+        let mut interpreter = Interpreter::new(Enviroment::new());
+        match interpreter.run(&ast) {
+            Err(Error::PermissionDenied(_)) => (),
+            res => panic!("Expected the clock to be denied but got {:?}", res),
+        }
+
+        interpreter.set_capabilities(Capabilities {
+            clock: true,
+            ..Capabilities::pure()
+        });
+        assert_eq!(interpreter.run(&ast), Ok(Object::Bool(true)));
+    }
+}
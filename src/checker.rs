@@ -0,0 +1,849 @@
+use crate::atom::{self, Atom, Function, Node, Struct, AST};
+use crate::enviroment::Enviroment;
+use crate::interpreter::Error;
+use crate::lexer::{Lexer, Span, Token};
+use crate::object::Object;
+use crate::parser::{self, Parser};
+use crate::stdlib;
+use crate::symbol::Symbol;
+use crate::types::Type;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+//An error the checker found without running the script
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub error: Error,
+}
+
+//Finds errors a script would definitely raise if the code ran, such as 1 + true or calling
+//a function that's never defined. Types are inferred from literals, annotations and the
+//functions and structs the script defines, anything that can't be known ahead of time is
+//assumed to be fine. Syntax errors are returned as the error.
+pub fn check(src: &str) -> parser::Result<Vec<Diagnostic>> {
+    let mut parser = Parser::new(Lexer::new(src));
+    parser.set_optimise(false);
+    let ast = parser.parse_program()?;
+
+    let mut env = Enviroment::new();
+    stdlib::load(&mut env);
+    let mut checker = Checker {
+        src,
+        env: &env,
+        globals: HashMap::new(),
+        scopes: vec![Scope::new(true)],
+        returns: HashMap::new(),
+        span: Span::default(),
+        diagnostics: vec![],
+    };
+    define_globals(&ast, &mut checker.globals);
+    checker.infer(&ast);
+
+    //Function bodies are checked at each definition and call, so errors inside them can
+    //be found more than once
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| (d.span.start, d.span.end));
+    diagnostics.dedup();
+    Ok(diagnostics)
+}
+
+//The 1 based line and column of a byte offset, columns count characters
+pub fn position(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[start..].chars().count() + 1)
+}
+
+//What is known about the value of an expression
+#[derive(Debug, Clone, PartialEq)]
+enum Inferred {
+    Unknown,
+    //A value of this concrete type
+    Of(Type),
+    //The type itself, which constructs or converts values when called
+    Type(Type),
+}
+
+impl Inferred {
+    fn ty(&self) -> Option<Type> {
+        match self {
+            Inferred::Unknown => None,
+            Inferred::Of(ty) => Some(ty.clone()),
+            Inferred::Type(_) => Some(Type::Type),
+        }
+    }
+
+    //A value that could come from either
+    fn join(self, other: Inferred) -> Inferred {
+        if self == other {
+            self
+        } else {
+            Inferred::Unknown
+        }
+    }
+}
+
+//How a global is assigned somewhere at the top level of the script
+enum Definition {
+    Function(Rc<Function>),
+    Struct(Rc<Struct>),
+    Value,
+}
+
+//The variables of the script or of a function call as they're checked in order
+struct Scope {
+    vars: HashMap<Symbol, Inferred>,
+    //Locals aren't tracked in functions that define closures as a call could change them
+    track: bool,
+    returns: Option<Inferred>,
+}
+
+impl Scope {
+    fn new(track: bool) -> Self {
+        Scope {
+            vars: HashMap::new(),
+            track,
+            returns: None,
+        }
+    }
+}
+
+struct Checker<'a> {
+    src: &'a str,
+    env: &'a Enviroment,
+    globals: HashMap<Symbol, Vec<Definition>>,
+    //The top level of the script followed by the functions being checked
+    scopes: Vec<Scope>,
+    //What each function returns, once its body has been checked
+    returns: HashMap<*const Function, Inferred>,
+    //The innermost expression with a span
+    span: Span,
+    diagnostics: Vec<Diagnostic>,
+}
+
+//Collects the globals assigned at the top level, function bodies only assign locals
+fn define_globals(ast: &AST, globals: &mut HashMap<Symbol, Vec<Definition>>) {
+    if let AST::Node(node) = ast {
+        let definition = match (&node.head, node.tail.as_slice()) {
+            (Atom::Assign, [AST::Atom(Atom::Symbol(name)), value]) => {
+                let definition = match value {
+                    AST::Atom(Atom::Function(function)) => Definition::Function(function.clone()),
+                    AST::Atom(Atom::Struct(ty)) => Definition::Struct(ty.clone()),
+                    _ => Definition::Value,
+                };
+                Some((*name, definition))
+            }
+            (Atom::Try, [_, AST::Atom(Atom::Symbol(name)), _]) => Some((*name, Definition::Value)),
+            _ => None,
+        };
+        if let Some((name, definition)) = definition {
+            globals.entry(name).or_default().push(definition);
+        }
+        for ast in node.tail.iter() {
+            define_globals(ast, globals);
+        }
+    }
+}
+
+//The variables assigned by an expression, not counting nested functions
+fn assigned(ast: &AST, names: &mut Vec<Symbol>) {
+    if let AST::Node(node) = ast {
+        let target = match (&node.head, node.tail.as_slice()) {
+            (Atom::Assign, [target, _]) | (Atom::Try, [_, target, _]) => Some(target),
+            _ => None,
+        };
+        match target {
+            Some(AST::Atom(Atom::Symbol(name))) => names.push(*name),
+            Some(AST::Atom(Atom::Local(local))) => names.push(local.name),
+            _ => (),
+        }
+        for ast in node.tail.iter() {
+            assigned(ast, names);
+        }
+    }
+}
+
+fn defines_function(ast: &AST) -> bool {
+    match ast {
+        AST::Atom(Atom::Function(_)) => true,
+        AST::Node(node) => node.tail.iter().any(defines_function),
+        AST::Atom(_) => false,
+    }
+}
+
+fn operator(atom: &Atom) -> &'static str {
+    match atom {
+        Atom::Plus => "+",
+        Atom::Minus => "-",
+        Atom::Multiply => "*",
+        Atom::Divide => "/",
+        Atom::Power => "^",
+        Atom::GreaterThan => ">",
+        _ => "<",
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, error: Error) {
+        self.diagnostics.push(Diagnostic {
+            span: self.span,
+            error,
+        });
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("The top level scope is never popped")
+    }
+
+    fn infer(&mut self, ast: &AST) -> Inferred {
+        atom::grow_stack(|| match ast {
+            AST::Atom(atom) => self.infer_atom(atom),
+            AST::Node(node) if node.span == Span::default() => self.infer_node(node),
+            AST::Node(node) => {
+                let outer = mem::replace(&mut self.span, node.span);
+                let inferred = self.infer_node(node);
+                self.span = outer;
+                inferred
+            }
+        })
+    }
+
+    fn infer_atom(&mut self, atom: &Atom) -> Inferred {
+        match atom {
+            Atom::Float(_) => Inferred::Of(Type::Float),
+            Atom::String(_) => Inferred::Of(Type::String),
+            Atom::True | Atom::False => Inferred::Of(Type::Bool),
+            Atom::Nothing => Inferred::Of(Type::Nothing),
+            Atom::Symbol(name) => self.read_global(*name),
+            Atom::Local(local) => match self.scopes.last() {
+                Some(scope) if local.depth == 0 && scope.track => scope
+                    .vars
+                    .get(&local.name)
+                    .cloned()
+                    .unwrap_or(Inferred::Unknown),
+                _ => Inferred::Unknown,
+            },
+            Atom::Function(function) => {
+                self.check_function(function);
+                Inferred::Of(Type::Function)
+            }
+            Atom::Struct(ty) => Inferred::Type(Type::Struct(ty.clone())),
+            _ => Inferred::Unknown,
+        }
+    }
+
+    //At the top level globals are known from the assignments checked so far, inside a
+    //function only those that are always the same thing are
+    fn read_global(&mut self, name: Symbol) -> Inferred {
+        if self.scopes.len() == 1 {
+            if let Some(inferred) = self.scopes[0].vars.get(&name) {
+                return inferred.clone();
+            }
+        }
+        match (self.globals.get(&name), self.env.get_symbol(name)) {
+            (None, None) => {
+                let span = self.locate(name);
+                self.diagnostics.push(Diagnostic {
+                    span,
+                    error: Error::UndefinedVariable(name.to_string()),
+                });
+                Inferred::Unknown
+            }
+            (None, Some(Object::Type(ty))) => Inferred::Type(ty.clone()),
+            (None, Some(builtin)) => Inferred::Of(Type::of(builtin)),
+            (Some(_), _) if self.methods(name).is_some() => Inferred::Of(Type::Function),
+            (Some(_), _) => match self.struct_named(name) {
+                Some(ty) => Inferred::Type(Type::Struct(ty)),
+                None => Inferred::Unknown,
+            },
+        }
+    }
+
+    //Where a name is used in the innermost expression, as names don't have spans of their own
+    fn locate(&self, name: Symbol) -> Span {
+        let start = self.span.start;
+        let mut lexer = Lexer::new(&self.src[start..self.span.end]);
+        while let (Ok(token), Ok(span)) = (lexer.peek(0), lexer.peek_span(0)) {
+            match token {
+                Token::EOF => break,
+                Token::Symbol(s) if s == &*name.as_str() => {
+                    return Span {
+                        start: start + span.start,
+                        end: start + span.end,
+                    }
+                }
+                _ => (),
+            }
+            let _ = lexer.advance();
+        }
+        self.span
+    }
+
+    fn write(&mut self, target: &AST, value: Inferred) {
+        let at_top = self.scopes.len() == 1;
+        let scope = self.scope();
+        match target {
+            AST::Atom(Atom::Symbol(name)) if at_top => {
+                scope.vars.insert(*name, value);
+            }
+            AST::Atom(Atom::Local(local)) if local.depth == 0 && scope.track => {
+                scope.vars.insert(local.name, value);
+            }
+            _ => (),
+        }
+    }
+
+    //Forgets what's known about the variables an expression assigns, for code that can
+    //run any number of times or stop part way through
+    fn forget(&mut self, asts: &[&AST]) {
+        let mut names = vec![];
+        for ast in asts.iter() {
+            assigned(ast, &mut names);
+        }
+        let scope = self.scope();
+        for name in names {
+            scope.vars.insert(name, Inferred::Unknown);
+        }
+    }
+
+    //The methods of a global that's only ever assigned functions, later definitions with
+    //the same parameter types replace earlier ones
+    fn methods(&self, name: Symbol) -> Option<Vec<Rc<Function>>> {
+        let mut methods: Vec<Rc<Function>> = vec![];
+        for definition in self.globals.get(&name)?.iter() {
+            match definition {
+                Definition::Function(function) => {
+                    methods.retain(|method| method.types != function.types);
+                    methods.push(function.clone());
+                }
+                _ => return None,
+            }
+        }
+        Some(methods)
+    }
+
+    fn struct_named(&self, name: Symbol) -> Option<Rc<Struct>> {
+        match self.globals.get(&name).map(Vec::as_slice) {
+            Some([Definition::Struct(ty)]) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    //The type an annotation refers to, if it can be known before running the script
+    fn resolve_type(&self, name: Symbol) -> Option<Type> {
+        if let Some(ty) = self.struct_named(name) {
+            return Some(Type::Struct(ty));
+        }
+        match (self.globals.get(&name), self.env.get_symbol(name)) {
+            (None, Some(Object::Type(ty))) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    //Checks the body of a function once, returning what calls to it return
+    fn check_function(&mut self, function: &Rc<Function>) -> Inferred {
+        let key = Rc::as_ptr(function);
+        if let Some(returns) = self.returns.get(&key) {
+            return returns.clone();
+        }
+        //Recursive calls return whatever the function does, which isn't known yet
+        self.returns.insert(key, Inferred::Unknown);
+
+        let mut scope = Scope::new(!defines_function(&function.body));
+        for (param, ty) in function.params.iter().zip(function.types.iter()) {
+            let inferred = match ty.and_then(|ty| self.resolve_type(ty)) {
+                Some(ty) if !ty.is_abstract() => Inferred::Of(ty),
+                _ => Inferred::Unknown,
+            };
+            scope.vars.insert(*param, inferred);
+        }
+        self.scopes.push(scope);
+        let outer = self.span;
+        let value = self.infer(&function.body);
+        self.span = outer;
+        let scope = self.scopes.pop().expect("The function's scope was pushed");
+
+        let returns = match scope.returns {
+            Some(returns) => returns.join(value),
+            None => value,
+        };
+        self.returns.insert(key, returns.clone());
+        returns
+    }
+
+    fn infer_node(&mut self, node: &Node) -> Inferred {
+        let tail = &node.tail;
+        match &node.head {
+            Atom::Minus if tail.len() == 1 => match self.infer(&tail[0]).ty() {
+                Some(Type::Float) => Inferred::Of(Type::Float),
+                Some(ty) if !matches!(ty, Type::Struct(_)) => {
+                    self.error(Error::TypeError(format!("Cannot negate a {}", ty)));
+                    Inferred::Unknown
+                }
+                _ => Inferred::Unknown,
+            },
+            Atom::Plus
+            | Atom::Minus
+            | Atom::Multiply
+            | Atom::Divide
+            | Atom::Power
+            | Atom::GreaterThan
+            | Atom::LessThan => {
+                let a = self.infer(&tail[0]).ty();
+                let b = self.infer(&tail[1]).ty();
+                let result = match node.head {
+                    Atom::GreaterThan | Atom::LessThan => Type::Bool,
+                    _ => Type::Float,
+                };
+                match (a, b) {
+                    (Some(Type::Float), Some(Type::Float)) => Inferred::Of(result),
+                    //Structs can have methods defined for operators
+                    (Some(Type::Struct(_)), _) | (_, Some(Type::Struct(_))) => Inferred::Unknown,
+                    (Some(a), Some(b)) => {
+                        self.error(Error::TypeError(format!(
+                            "Cannot evaluate {} {} {}",
+                            a,
+                            operator(&node.head),
+                            b
+                        )));
+                        Inferred::Unknown
+                    }
+                    _ => Inferred::Unknown,
+                }
+            }
+            Atom::Equal | Atom::NotEqual => {
+                self.infer(&tail[0]);
+                self.infer(&tail[1]);
+                Inferred::Of(Type::Bool)
+            }
+            Atom::Assign => self.infer_assign(&tail[0], &tail[1]),
+            Atom::Block => {
+                let mut value = Inferred::Of(Type::Nothing);
+                for statement in tail.iter() {
+                    value = self.infer(statement);
+                }
+                value
+            }
+            Atom::If | Atom::ElseIf => {
+                self.check_condition(&tail[0], "if statement");
+                let before = self.scope().vars.clone();
+                let then = self.infer(&tail[1]);
+                let after_then = mem::replace(&mut self.scope().vars, before);
+                let other = self.infer(&tail[2]);
+
+                //Only what both branches agree on is known afterwards
+                let vars = &mut self.scope().vars;
+                for (name, value) in after_then {
+                    let joined = match vars.get(&name) {
+                        Some(other) => value.join(other.clone()),
+                        None => Inferred::Unknown,
+                    };
+                    vars.insert(name, joined);
+                }
+                then.join(other)
+            }
+            Atom::While => {
+                self.forget(&[&tail[0], &tail[1]]);
+                self.check_condition(&tail[0], "while loop");
+                self.infer(&tail[1]);
+                Inferred::Of(Type::Nothing)
+            }
+            Atom::Try => {
+                self.forget(&[&tail[0]]);
+                let body = self.infer(&tail[0]);
+                self.forget(&[&tail[0]]);
+                self.write(&tail[1], Inferred::Unknown);
+                let handler = self.infer(&tail[2]);
+                self.forget(&[&tail[2]]);
+                body.join(handler)
+            }
+            Atom::Finally => {
+                let value = self.infer(&tail[0]);
+                self.infer(&tail[1]);
+                value
+            }
+            Atom::Return => {
+                let value = self.infer(&tail[0]);
+                if self.scopes.len() > 1 {
+                    let scope = self.scope();
+                    scope.returns = Some(match scope.returns.take() {
+                        Some(returns) => returns.join(value.clone()),
+                        None => value.clone(),
+                    });
+                }
+                value
+            }
+            Atom::Array => {
+                for ast in tail.iter() {
+                    self.infer(ast);
+                }
+                Inferred::Of(Type::Array)
+            }
+            Atom::Interpolate => {
+                for ast in tail.iter() {
+                    self.infer(ast);
+                }
+                Inferred::Of(Type::String)
+            }
+            Atom::Index => {
+                let target = self.infer(&tail[0]).ty();
+                self.infer(&tail[1]);
+                self.check_index(target)
+            }
+            Atom::Field(field) => {
+                let target = self.infer(&tail[0]).ty();
+                self.check_field(target, *field, false);
+                Inferred::Unknown
+            }
+            Atom::Call => self.infer_call(tail),
+            _ => {
+                for ast in tail.iter() {
+                    self.infer(ast);
+                }
+                Inferred::Unknown
+            }
+        }
+    }
+
+    fn infer_assign(&mut self, target: &AST, value: &AST) -> Inferred {
+        let value = self.infer(value);
+        match target {
+            AST::Node(node) if node.head == Atom::Index => {
+                let target = self.infer(&node.tail[0]).ty();
+                self.infer(&node.tail[1]);
+                self.check_index(target);
+                value
+            }
+            AST::Node(node) => {
+                let field = match node.head {
+                    Atom::Field(field) => field,
+                    _ => return value,
+                };
+                let target = self.infer(&node.tail[0]).ty();
+                self.check_field(target, field, true);
+                value
+            }
+            target => {
+                self.write(target, value);
+                Inferred::Of(Type::Symbol)
+            }
+        }
+    }
+
+    fn check_condition(&mut self, condition: &AST, statement: &str) {
+        match self.infer(condition).ty() {
+            Some(Type::Bool) | None => (),
+            Some(ty) => self.error(Error::TypeError(format!(
+                "Non boolean value given to {}, a {}",
+                statement, ty
+            ))),
+        }
+    }
+
+    //Arrays, strings and the environment can be indexed, as can structs with a getindex
+    //method
+    fn check_index(&mut self, target: Option<Type>) -> Inferred {
+        match target {
+            Some(Type::String) => Inferred::Of(Type::String),
+            Some(Type::Array) | Some(Type::Env) | Some(Type::Struct(_)) | None => Inferred::Unknown,
+            Some(ty) => {
+                self.error(Error::TypeError(format!("Cannot index into a {}", ty)));
+                Inferred::Unknown
+            }
+        }
+    }
+
+    fn check_field(&mut self, target: Option<Type>, field: Symbol, assign: bool) {
+        let error = match target {
+            Some(Type::Struct(ty)) if !ty.fields.contains(&field) => {
+                format!("{} has no field {}", ty.name, field)
+            }
+            Some(Type::Struct(ty)) if assign && !ty.mutable => format!(
+                "Cannot assign to the field {} as {} is immutable",
+                field, ty.name
+            ),
+            Some(Type::Struct(_)) | None => return,
+            Some(Type::Error) if !assign => match &*field.as_str() {
+                "kind" | "message" | "stacktrace" => return,
+                _ => format!("Error has no field {}", field),
+            },
+            Some(ty) if assign => format!("Cannot assign to the field {} of a {}", field, ty),
+            Some(ty) => format!("{} has no field {}", ty, field),
+        };
+        self.error(Error::TypeError(error));
+    }
+
+    fn infer_call(&mut self, tail: &[AST]) -> Inferred {
+        let function = self.infer(&tail[0]);
+        let args: Vec<Inferred> = tail[1..].iter().map(|arg| self.infer(arg)).collect();
+
+        let methods = match &tail[0] {
+            AST::Atom(Atom::Symbol(name)) => self.methods(*name),
+            _ => None,
+        };
+        if let Some(methods) = methods {
+            return self.check_methods(&methods, &args);
+        }
+
+        match function {
+            Inferred::Type(Type::Struct(ty)) => {
+                if args.len() != ty.fields.len() {
+                    self.error(Error::ArgumentError(format!(
+                        "{} expects {} argument(s) but was given {}",
+                        ty.name,
+                        ty.fields.len(),
+                        args.len()
+                    )));
+                }
+                Inferred::Of(Type::Struct(ty))
+            }
+            Inferred::Type(Type::Float) => Inferred::Of(Type::Float),
+            Inferred::Of(Type::Function) | Inferred::Type(_) | Inferred::Unknown => {
+                Inferred::Unknown
+            }
+            Inferred::Of(ty) => {
+                self.error(Error::TypeError(format!("A {} is not callable", ty)));
+                Inferred::Unknown
+            }
+        }
+    }
+
+    //Picks the methods a call could run, the same way the interpreter does when it knows
+    //the types of all the arguments
+    fn check_methods(&mut self, methods: &[Rc<Function>], args: &[Inferred]) -> Inferred {
+        let name = methods[0].name;
+        let candidates: Vec<&Rc<Function>> = methods
+            .iter()
+            .filter(|method| method.params.len() == args.len())
+            .collect();
+        //Arguments whose type isn't known are shown as Any
+        let no_method = || {
+            let types: Vec<String> = args
+                .iter()
+                .map(|arg| arg.ty().unwrap_or(Type::Any).to_string())
+                .collect();
+            let signatures: Vec<String> = methods.iter().map(|m| m.signature()).collect();
+            Error::MethodError(format!(
+                "no method matching {}({}), the candidates are {}",
+                name,
+                types.join(", "),
+                signatures.join(", ")
+            ))
+        };
+        if candidates.is_empty() {
+            self.error(match methods {
+                [method] => Error::ArgumentError(format!(
+                    "{} expects {} argument(s) but was given {}",
+                    name,
+                    method.params.len(),
+                    args.len()
+                )),
+                _ => no_method(),
+            });
+            return Inferred::Unknown;
+        }
+
+        let types: Option<Vec<Type>> = args.iter().map(Inferred::ty).collect();
+        let types = match types {
+            Some(types) => types,
+            None => {
+                return candidates
+                    .into_iter()
+                    .map(|method| self.check_function(method))
+                    .fold(None, |joined: Option<Inferred>, returns| match joined {
+                        Some(joined) => Some(joined.join(returns)),
+                        None => Some(returns),
+                    })
+                    .unwrap_or(Inferred::Unknown)
+            }
+        };
+
+        //Annotations that can't be resolved yet might match anything
+        let mut applicable = vec![];
+        for method in candidates.into_iter() {
+            let mut signature = vec![];
+            for (ty, arg) in method.types.iter().zip(types.iter()) {
+                match ty.map(|ty| self.resolve_type(ty)) {
+                    None => signature.push(Some(Type::Any)),
+                    Some(Some(ty)) if arg.is_subtype(&ty) => signature.push(Some(ty)),
+                    Some(Some(_)) => break,
+                    Some(None) => signature.push(None),
+                }
+            }
+            if signature.len() == types.len() {
+                applicable.push((method, signature));
+            }
+        }
+        if applicable.is_empty() {
+            self.error(no_method());
+            return Inferred::Unknown;
+        }
+
+        let best = applicable.iter().find(|(_, a)| {
+            applicable.iter().all(|(_, b)| {
+                a.iter().zip(b).all(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => a.is_subtype(b),
+                    _ => false,
+                })
+            })
+        });
+        match best {
+            Some((method, _)) => {
+                let method = Rc::clone(method);
+                self.check_function(&method)
+            }
+            None => Inferred::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    //Each error as line:column: error
+    fn errors(src: &str) -> Vec<String> {
+        check(src)
+            .expect("The test script should parse")
+            .iter()
+            .map(|d| {
+                let (line, column) = position(src, d.span.start);
+                format!("{}:{}: {}", line, column, d.error)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn positions() {
+        let src = "a\nbc αd";
+        assert_eq!(position(src, 0), (1, 1));
+        assert_eq!(position(src, 3), (2, 2));
+        assert_eq!(position(src, 7), (2, 5));
+    }
+
+    #[test]
+    fn type_errors() {
+        let src = indoc!(
+            r#"
+            x = 1 + true
+            y = "a" * 2
+            if 1 then 2 end
+            z = [1][1] + "b"
+            s = "abc"
+            -s
+            s[1] - 1
+            n = nothing
+            n[1]
+            5(1)
+        "#
+        );
+        assert_eq!(
+            errors(src),
+            vec![
+                "1:5: TypeError: Cannot evaluate Float + Bool",
+                "2:5: TypeError: Cannot evaluate String * Float",
+                "3:1: TypeError: Non boolean value given to if statement, a Float",
+                "6:1: TypeError: Cannot negate a String",
+                "7:1: TypeError: Cannot evaluate String - Float",
+                "9:1: TypeError: Cannot index into a Nothing",
+                "10:1: TypeError: A Float is not callable",
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_names() {
+        let src = indoc!(
+            "
+            function f(x)
+                y = x + missing
+                helper(y)
+            end
+            function helper(y) y end
+            later = 1
+            total = f(2) + later
+            println(undefined_function(total))
+        "
+        );
+        assert_eq!(
+            errors(src),
+            vec![
+                "2:13: UndefinedVariable: missing is not defined",
+                "8:9: UndefinedVariable: undefined_function is not defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_values_are_allowed() {
+        let src = indoc!(
+            r#"
+            x = 1
+            if time() > 0 then x = "a" end
+            x + 1
+            y = 1
+            while y < 10
+                y = y + 1
+            end
+            y = "a"
+            function f(a) a + 1 end
+            f("a")
+            try
+                z = 1
+                z = "a"
+            catch e
+                e.kind
+            end
+            z + 1
+        "#
+        );
+        assert_eq!(errors(src), Vec::<String>::new());
+    }
+
+    #[test]
+    fn functions_and_structs() {
+        let src = indoc!(
+            r#"
+            struct Point x; y end
+            mutable struct Counter n end
+            function norm(p::Point) p.x * p.x + p.y * p.y end
+            function norm(x::Float) x end
+            function label(x::Float) "number" end
+            p = Point(1)
+            p.z
+            p.x = 2
+            c = Counter(0)
+            c.n = 1
+            norm("a")
+            norm(1, 2)
+            label(1) + 1
+            label(1, 2)
+            norm(Point(1, 2)) + 1
+            Float(true) + norm(2)
+        "#
+        );
+        assert_eq!(
+            errors(src),
+            vec![
+                "6:5: ArgumentError: Point expects 2 argument(s) but was given 1",
+                "7:1: TypeError: Point has no field z",
+                "8:1: TypeError: Cannot assign to the field x as Point is immutable",
+                "11:1: MethodError: no method matching norm(String), the candidates are norm(p::Point), norm(x::Float)",
+                "12:1: MethodError: no method matching norm(Float, Float), the candidates are norm(p::Point), norm(x::Float)",
+                "13:1: TypeError: Cannot evaluate String + Float",
+                "14:1: ArgumentError: label expects 1 argument(s) but was given 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(check("x = (1").is_err());
+    }
+}
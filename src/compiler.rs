@@ -0,0 +1,505 @@
+use crate::atom::{self, Atom, Function, Local, Node, AST};
+use crate::interpreter;
+use crate::object::Object;
+use crate::symbol::Symbol;
+use crate::types::Type;
+use std::fmt;
+use std::rc::Rc;
+
+//The instructions run by the vm. Operands index into the chunk's constants and
+//functions, jumps are absolute offsets into its code.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    Constant(u32),
+    Nothing,
+    True,
+    False,
+    GetGlobal(Symbol),
+    //Stores the value on top of the stack and replaces it with the variable's name, which
+    //is what an assignment evaluates to
+    SetGlobal(Symbol),
+    //Set the variable to the function on top of the stack, adding it as a method if the
+    //variable holds a function of the same name
+    DefineGlobal(Symbol),
+    GetLocal(Local),
+    SetLocal(Local),
+    DefineLocal(Local),
+    Pop,
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    Jump(u32),
+    //Pops a condition, the name of the statement is used in the error for a non boolean
+    JumpIfFalse(u32, &'static str),
+    Array(u32),
+    Interpolate(u32),
+    Index,
+    //Pops the value, target and index, leaving the value
+    SetIndex,
+    GetField(Symbol),
+    //Pops the value and target, leaving the value
+    SetField(Symbol),
+    Call(u32),
+    //A call in tail position, a scripted function replaces the caller's call frame
+    TailCall(u32),
+    Closure(u32),
+    //Errors raised until the matching EndTry jump to the handler with the caught value on
+    //the stack, unless they are limits set by the host
+    Try(u32),
+    //Like Try but the handler is a finally block, which return statements also run
+    Finally(u32),
+    EndTry,
+    //Raises the value on top of the stack again, used at the end of a finally block
+    Rethrow,
+    //Pops the caught value into a global for the length of a catch block, saving the
+    //global's value, which UnbindGlobal puts back
+    BindGlobal(Symbol),
+    UnbindGlobal(Symbol),
+    //Raises a TypeError with the message in the constant, for code the parser accepts
+    //but that can't run, such as 1 = 2
+    TypeError(u32),
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Object>,
+    pub functions: Vec<Rc<Function>>,
+}
+
+//Compiles a top level script, leaving its value on the stack for Return
+pub fn compile(ast: &AST) -> Chunk {
+    let mut chunk = Chunk::default();
+    chunk.compile(ast);
+    chunk.code.push(Op::Return);
+    chunk
+}
+
+//Compiles a function body, where calls in tail position become tail calls
+pub fn compile_function(function: &Function) -> Chunk {
+    let mut chunk = Chunk::default();
+    chunk.compile_tail(&function.body);
+    chunk.code.push(Op::Return);
+    chunk
+}
+
+impl Chunk {
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn constant(&mut self, object: Object) {
+        let idx = self.constants.len() as u32;
+        self.constants.push(object);
+        self.emit(Op::Constant(idx));
+    }
+
+    fn fail(&mut self, message: String) -> usize {
+        let idx = self.constants.len() as u32;
+        self.constants.push(Object::String(message));
+        self.emit(Op::TypeError(idx))
+    }
+
+    //Points the jump at `idx` to the next instruction to be emitted
+    fn patch(&mut self, idx: usize) {
+        let target = self.code.len() as u32;
+        match &mut self.code[idx] {
+            Op::Jump(to) | Op::JumpIfFalse(to, _) | Op::Try(to) | Op::Finally(to) => *to = target,
+            op => panic!("Cannot patch {:?} as it isn't a jump", op),
+        }
+    }
+
+    fn compile_all(&mut self, nodes: &[AST]) -> u32 {
+        for node in nodes.iter() {
+            self.compile(node);
+        }
+        nodes.len() as u32
+    }
+
+    fn compile_tail(&mut self, ast: &AST) {
+        atom::grow_stack(|| self.compile_tail_node(ast))
+    }
+
+    fn compile_tail_node(&mut self, ast: &AST) {
+        let node = match ast {
+            AST::Node(node) => node,
+            ast => return self.compile(ast),
+        };
+
+        let tail = &node.tail;
+        match &node.head {
+            Atom::Block if !tail.is_empty() => {
+                let (last, statements) = tail.split_last().expect("The block isn't empty");
+                for statement in statements.iter() {
+                    self.compile(statement);
+                    self.emit(Op::Pop);
+                }
+                self.compile_tail(last);
+            }
+            Atom::If | Atom::ElseIf => self.compile_if(tail, Self::compile_tail),
+            //Returning a call from a function is a tail call
+            Atom::Return => self.compile_tail(&tail[0]),
+            Atom::Field(field) => {
+                self.compile(&tail[0]);
+                self.emit(Op::GetField(*field));
+            }
+            Atom::Call => {
+                let n = self.compile_all(tail);
+                self.emit(Op::TailCall(n - 1));
+            }
+            _ => self.compile(ast),
+        }
+    }
+
+    fn compile_if(&mut self, tail: &[AST], branch: fn(&mut Self, &AST)) {
+        self.compile(&tail[0]);
+        let otherwise = self.emit(Op::JumpIfFalse(0, "if statement"));
+        branch(self, &tail[1]);
+        let end = self.emit(Op::Jump(0));
+        self.patch(otherwise);
+        branch(self, &tail[2]);
+        self.patch(end);
+    }
+
+    fn compile(&mut self, ast: &AST) {
+        atom::grow_stack(|| self.compile_node(ast))
+    }
+
+    fn compile_node(&mut self, ast: &AST) {
+        let node = match ast {
+            AST::Node(node) => node,
+            AST::Atom(atom) => {
+                match atom {
+                    Atom::Float(value) => self.constant(Object::Float(*value)),
+                    Atom::String(value) => self.constant(Object::String(value.clone())),
+                    Atom::Symbol(key) => {
+                        self.emit(Op::GetGlobal(*key));
+                    }
+                    Atom::Local(local) => {
+                        self.emit(Op::GetLocal(*local));
+                    }
+                    Atom::Function(function) => {
+                        let idx = self.functions.len() as u32;
+                        self.functions.push(function.clone());
+                        self.emit(Op::Closure(idx));
+                    }
+                    Atom::Struct(definition) => {
+                        self.constant(Object::Type(Type::Struct(definition.clone())))
+                    }
+                    Atom::False => {
+                        self.emit(Op::False);
+                    }
+                    Atom::True => {
+                        self.emit(Op::True);
+                    }
+                    Atom::Nothing => {
+                        self.emit(Op::Nothing);
+                    }
+                    atom => {
+                        self.fail(interpreter::cannot_evaluate(atom));
+                    }
+                }
+                return;
+            }
+        };
+
+        let tail = &node.tail;
+        match &node.head {
+            Atom::Minus if tail.len() == 1 => {
+                self.compile(&tail[0]);
+                self.emit(Op::Negate);
+            }
+            Atom::Plus
+            | Atom::Minus
+            | Atom::Divide
+            | Atom::Multiply
+            | Atom::Power
+            | Atom::GreaterThan
+            | Atom::LessThan
+            | Atom::Equal
+            | Atom::NotEqual => {
+                self.compile(&tail[0]);
+                self.compile(&tail[1]);
+                self.emit(match &node.head {
+                    Atom::Plus => Op::Add,
+                    Atom::Minus => Op::Subtract,
+                    Atom::Divide => Op::Divide,
+                    Atom::Multiply => Op::Multiply,
+                    Atom::Power => Op::Power,
+                    Atom::GreaterThan => Op::GreaterThan,
+                    Atom::LessThan => Op::LessThan,
+                    Atom::Equal => Op::Equal,
+                    _ => Op::NotEqual,
+                });
+            }
+            Atom::Assign => {
+                self.compile(&tail[1]);
+                let definition = matches!(tail[1], AST::Atom(Atom::Function(_)));
+                match &tail[0] {
+                    AST::Atom(Atom::Symbol(k)) if definition => self.emit(Op::DefineGlobal(*k)),
+                    AST::Atom(Atom::Local(local)) if definition => {
+                        self.emit(Op::DefineLocal(*local))
+                    }
+                    AST::Atom(Atom::Symbol(k)) => self.emit(Op::SetGlobal(*k)),
+                    AST::Atom(Atom::Local(local)) => self.emit(Op::SetLocal(*local)),
+                    AST::Node(Node {
+                        head: Atom::Index,
+                        tail,
+                        ..
+                    }) => {
+                        self.compile_all(tail);
+                        self.emit(Op::SetIndex)
+                    }
+                    AST::Node(Node {
+                        head: Atom::Field(field),
+                        tail,
+                        ..
+                    }) => {
+                        self.compile(&tail[0]);
+                        self.emit(Op::SetField(*field))
+                    }
+                    key => self.fail(interpreter::cannot_assign(key)),
+                };
+            }
+            Atom::Block => match tail.split_last() {
+                None => {
+                    self.emit(Op::Nothing);
+                }
+                Some((last, statements)) => {
+                    for statement in statements.iter() {
+                        self.compile(statement);
+                        self.emit(Op::Pop);
+                    }
+                    self.compile(last);
+                }
+            },
+            Atom::If | Atom::ElseIf => self.compile_if(tail, Self::compile),
+            Atom::While => {
+                let start = self.code.len() as u32;
+                self.compile(&tail[0]);
+                let exit = self.emit(Op::JumpIfFalse(0, "while loop"));
+                self.compile(&tail[1]);
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.emit(Op::Nothing);
+            }
+            Atom::Try => {
+                let handler = self.emit(Op::Try(0));
+                self.compile(&tail[0]);
+                self.emit(Op::EndTry);
+                let end = self.emit(Op::Jump(0));
+                self.patch(handler);
+                match &tail[1] {
+                    //The global is put back however the catch block is left
+                    AST::Atom(Atom::Symbol(k)) => {
+                        self.emit(Op::BindGlobal(*k));
+                        let cleanup = self.emit(Op::Finally(0));
+                        self.compile(&tail[2]);
+                        self.emit(Op::EndTry);
+                        self.emit(Op::UnbindGlobal(*k));
+                        let done = self.emit(Op::Jump(0));
+                        self.patch(cleanup);
+                        self.emit(Op::UnbindGlobal(*k));
+                        self.emit(Op::Rethrow);
+                        self.patch(done);
+                    }
+                    AST::Atom(Atom::Local(local)) => {
+                        self.emit(Op::SetLocal(*local));
+                        self.emit(Op::Pop);
+                        self.compile(&tail[2]);
+                    }
+                    _ => {
+                        self.emit(Op::Pop);
+                        self.compile(&tail[2]);
+                    }
+                }
+                self.patch(end);
+            }
+            Atom::Return => {
+                self.compile(&tail[0]);
+                self.emit(Op::Return);
+            }
+            //The cleanup is compiled twice, once for when the block completes and once
+            //for when it raises an error
+            Atom::Finally => {
+                let handler = self.emit(Op::Finally(0));
+                self.compile(&tail[0]);
+                self.emit(Op::EndTry);
+                self.compile(&tail[1]);
+                self.emit(Op::Pop);
+                let end = self.emit(Op::Jump(0));
+                self.patch(handler);
+                self.compile(&tail[1]);
+                self.emit(Op::Pop);
+                self.emit(Op::Rethrow);
+                self.patch(end);
+            }
+            Atom::Array => {
+                let n = self.compile_all(tail);
+                self.emit(Op::Array(n));
+            }
+            Atom::Interpolate => {
+                let n = self.compile_all(tail);
+                self.emit(Op::Interpolate(n));
+            }
+            Atom::Index => {
+                self.compile_all(tail);
+                self.emit(Op::Index);
+            }
+            Atom::Field(field) => {
+                self.compile(&tail[0]);
+                self.emit(Op::GetField(*field));
+            }
+            Atom::Call => {
+                let n = self.compile_all(tail);
+                self.emit(Op::Call(n - 1));
+            }
+            op => {
+                self.fail(interpreter::cannot_evaluate(op));
+            }
+        }
+    }
+}
+
+//Disassembles the chunk followed by the functions defined in it, one instruction a line
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, op) in self.code.iter().enumerate() {
+            write!(f, "{:04} ", i)?;
+            match op {
+                Op::Constant(idx) => {
+                    writeln!(
+                        f,
+                        "Constant {} ({})",
+                        idx,
+                        self.constants[*idx as usize].repr()
+                    )
+                }
+                Op::GetGlobal(k) => writeln!(f, "GetGlobal {}", k),
+                Op::SetGlobal(k) => writeln!(f, "SetGlobal {}", k),
+                Op::BindGlobal(k) => writeln!(f, "BindGlobal {}", k),
+                Op::UnbindGlobal(k) => writeln!(f, "UnbindGlobal {}", k),
+                Op::DefineGlobal(k) => writeln!(f, "DefineGlobal {}", k),
+                Op::GetField(k) => writeln!(f, "GetField {}", k),
+                Op::SetField(k) => writeln!(f, "SetField {}", k),
+                Op::GetLocal(l) => writeln!(f, "GetLocal {} {} ({})", l.depth, l.slot, l.name),
+                Op::SetLocal(l) => writeln!(f, "SetLocal {} {} ({})", l.depth, l.slot, l.name),
+                Op::DefineLocal(l) => {
+                    writeln!(f, "DefineLocal {} {} ({})", l.depth, l.slot, l.name)
+                }
+                Op::Jump(to) => writeln!(f, "Jump {:04}", to),
+                Op::Try(to) => writeln!(f, "Try {:04}", to),
+                Op::Finally(to) => writeln!(f, "Finally {:04}", to),
+                Op::JumpIfFalse(to, _) => writeln!(f, "JumpIfFalse {:04}", to),
+                Op::Array(n) => writeln!(f, "Array {}", n),
+                Op::Interpolate(n) => writeln!(f, "Interpolate {}", n),
+                Op::Call(n) => writeln!(f, "Call {}", n),
+                Op::TailCall(n) => writeln!(f, "TailCall {}", n),
+                Op::TypeError(idx) => {
+                    writeln!(
+                        f,
+                        "TypeError {} ({})",
+                        idx,
+                        self.constants[*idx as usize].repr()
+                    )
+                }
+                Op::Closure(idx) => {
+                    writeln!(
+                        f,
+                        "Closure {} ({})",
+                        idx, self.functions[*idx as usize].name
+                    )
+                }
+                op => writeln!(f, "{:?}", op),
+            }?;
+        }
+
+        for function in self.functions.iter() {
+            let params: Vec<String> = function.params.iter().map(|p| p.to_string()).collect();
+            writeln!(f, "\nfunction {}({})", function.name, params.join(", "))?;
+            write!(f, "{}", compile_function(function))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use indoc::indoc;
+
+    fn disassemble(s: &str) -> String {
+        let ast = Parser::new(Lexer::new(s)).parse_block().unwrap();
+        compile(&ast).to_string()
+    }
+
+    #[test]
+    fn if_statement() {
+        let code = disassemble(r#"if x > 1 then "big" else nothing end"#);
+        let ans = indoc!(
+            r#"
+            0000 GetGlobal x
+            0001 Constant 0 (1)
+            0002 GreaterThan
+            0003 JumpIfFalse 0006
+            0004 Constant 1 ("big")
+            0005 Jump 0007
+            0006 GetGlobal nothing
+            0007 Return
+            "#
+        );
+        assert_eq!(code, ans);
+    }
+
+    #[test]
+    fn functions_and_loops() {
+        let s = indoc!(
+            "
+            function down(n)
+                while n > 0
+                    n = n - 1
+                end
+            end
+            down(3)
+        "
+        );
+        let ans = indoc!(
+            "
+            0000 Closure 0 (down)
+            0001 DefineGlobal down
+            0002 Pop
+            0003 GetGlobal down
+            0004 Constant 0 (3)
+            0005 Call 1
+            0006 Return
+
+            function down(n)
+            0000 GetLocal 0 0 (n)
+            0001 Constant 0 (0)
+            0002 GreaterThan
+            0003 JumpIfFalse 0010
+            0004 GetLocal 0 0 (n)
+            0005 Constant 1 (1)
+            0006 Subtract
+            0007 SetLocal 0 0 (n)
+            0008 Pop
+            0009 Jump 0000
+            0010 Nothing
+            0011 Return
+            "
+        );
+        assert_eq!(disassemble(s), ans);
+    }
+}
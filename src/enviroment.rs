@@ -1,11 +1,20 @@
 use crate::object::Object;
+use crate::symbol::Symbol;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+//Global variables, looked up by symbol from scripts and by name from the embedding API
 #[derive(Debug)]
 pub struct Enviroment {
     parent: Option<Rc<Enviroment>>,
-    state: HashMap<String, Object>,
+    state: HashMap<Symbol, Object>,
+}
+
+impl Default for Enviroment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Enviroment {
@@ -18,16 +27,68 @@ impl Enviroment {
     }
 
     pub fn get(&self, key: &str) -> Option<&Object> {
-        match self.state.get(key) {
+        self.get_symbol(Symbol::lookup(key)?)
+    }
+
+    pub fn get_symbol(&self, key: Symbol) -> Option<&Object> {
+        match self.state.get(&key) {
             Some(value) => Some(value),
             None => match &self.parent {
                 None => None,
-                Some(parent) => parent.get(key),
+                Some(parent) => parent.get_symbol(key),
             },
         }
     }
 
     pub fn insert(&mut self, key: &str, value: Object) {
-        self.state.insert(key.to_string(), value);
+        self.insert_symbol(Symbol::intern(key), value);
+    }
+
+    pub fn insert_symbol(&mut self, key: Symbol, value: Object) {
+        self.state.insert(key, value);
+    }
+
+    //Sets or removes a global, giving back what it replaced so it can be put back later
+    pub fn replace_symbol(&mut self, key: Symbol, value: Option<Object>) -> Option<Object> {
+        match value {
+            Some(value) => self.state.insert(key, value),
+            None => self.state.remove(&key),
+        }
+    }
+}
+
+//The local variables of a function call. Closures keep the frame they were defined in
+//alive through `parent`, a slot is None until the variable is assigned.
+#[derive(Debug)]
+pub struct Frame {
+    slots: RefCell<Vec<Option<Object>>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    pub fn new(size: usize, parent: Option<Rc<Frame>>) -> Self {
+        Frame {
+            slots: RefCell::new(vec![None; size]),
+            parent,
+        }
+    }
+
+    pub fn get(&self, depth: usize, slot: usize) -> Option<Object> {
+        self.ancestor(depth).slots.borrow()[slot].clone()
+    }
+
+    pub fn set(&self, depth: usize, slot: usize, value: Object) {
+        self.ancestor(depth).slots.borrow_mut()[slot] = Some(value);
+    }
+
+    fn ancestor(&self, depth: usize) -> &Frame {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame
+                .parent
+                .as_ref()
+                .expect("Resolved locals never reach past the outermost frame");
+        }
+        frame
     }
 }
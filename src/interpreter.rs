@@ -1,112 +1,1259 @@
-use crate::atom::{Atom, AST};
-use crate::enviroment::Enviroment;
-use crate::object::Object;
+use crate::atom::{self, Atom, Function, Node, AST};
+use crate::capabilities::Capabilities;
+use crate::compiler::Chunk;
+use crate::enviroment::{Enviroment, Frame};
+use crate::object::{Closure, ErrorValue, Generic, Instance, Object};
+use crate::stdlib;
+use crate::symbol::Symbol;
+use crate::types::Type;
+use crate::vm;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-struct Interpreter {
-    env: Enviroment,
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UndefinedVariable(String),
+    TypeError(String),
+    ArgumentError(String),
+    //No method of a function matches the types of the arguments
+    MethodError(String),
+    DomainError(String),
+    BoundsError(String),
+    KeyError(String),
+    FormatError(String),
+    IOError(String),
+    StackOverflow(String),
+    //Raised by error("msg")
+    ErrorException(String),
+    //A value other than an error passed to throw
+    Thrown(Object),
+    //Unwinds a return statement to the function call it returns from, scripts and the
+    //host never see it
+    Return(Object),
+    //The limits a host sets on a script, see Interpreter::set_fuel, set_timeout and
+    //interrupt_handle
+    OutOfFuel(String),
+    Timeout(String),
+    Interrupted(String),
+    OutOfMemory(String),
+    //A builtin needed a capability the script wasn't given
+    PermissionDenied(String),
+}
+
+impl Error {
+    //The name scripts see for the error when they catch it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UndefinedVariable(_) => "UndefinedVariable",
+            Error::TypeError(_) => "TypeError",
+            Error::ArgumentError(_) => "ArgumentError",
+            Error::MethodError(_) => "MethodError",
+            Error::DomainError(_) => "DomainError",
+            Error::BoundsError(_) => "BoundsError",
+            Error::KeyError(_) => "KeyError",
+            Error::FormatError(_) => "FormatError",
+            Error::IOError(_) => "IOError",
+            Error::StackOverflow(_) => "StackOverflow",
+            Error::ErrorException(_) => "ErrorException",
+            Error::Thrown(_) => "Thrown",
+            Error::Return(_) => "Return",
+            Error::OutOfFuel(_) => "OutOfFuel",
+            Error::Timeout(_) => "Timeout",
+            Error::Interrupted(_) => "Interrupted",
+            Error::OutOfMemory(_) => "OutOfMemory",
+            Error::PermissionDenied(_) => "PermissionDenied",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::UndefinedVariable(name) => format!("{} is not defined", name),
+            Error::Thrown(object) | Error::Return(object) => object.repr(),
+            Error::TypeError(msg)
+            | Error::ArgumentError(msg)
+            | Error::MethodError(msg)
+            | Error::DomainError(msg)
+            | Error::BoundsError(msg)
+            | Error::KeyError(msg)
+            | Error::FormatError(msg)
+            | Error::IOError(msg)
+            | Error::StackOverflow(msg)
+            | Error::ErrorException(msg)
+            | Error::OutOfFuel(msg)
+            | Error::Timeout(msg)
+            | Error::Interrupted(msg)
+            | Error::OutOfMemory(msg)
+            | Error::PermissionDenied(msg) => msg.clone(),
+        }
+    }
+
+    //Hitting a limit set by the host stops the script, try blocks can't catch it and
+    //finally blocks aren't run
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            Error::OutOfFuel(_) | Error::Timeout(_) | Error::Interrupted(_) | Error::OutOfMemory(_)
+        )
+    }
+
+    pub fn is_catchable(&self) -> bool {
+        !self.is_limit() && !matches!(self, Error::Return(_))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl error::Error for Error {}
+
+//Deep recursion is almost always a bug, this leaves plenty of room for recursive
+//algorithms while failing quickly on runaway ones
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+//Reading the clock is slow compared to a step so the deadline is checked every so often
+const DEADLINE_INTERVAL: u64 = 1024;
+
+//How `Interpreter::run` executes a script, both give the same results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    TreeWalker,
+    Bytecode,
+}
+
+//Stops a running script from another thread, it fails with Error::Interrupted at its
+//next step
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+pub struct Interpreter {
+    pub(crate) env: Enviroment,
+    //The frame of the function being called, None at the top level
+    frame: Option<Rc<Frame>>,
+    backend: Backend,
+    //Function bodies compiled by the vm, the function is kept so its address isn't reused
+    pub(crate) compiled: HashMap<*const Function, (Rc<Function>, Rc<Chunk>)>,
+    //The scripted functions being called, outermost first
+    pub(crate) backtrace: Vec<Symbol>,
+    max_depth: usize,
+    //Steps left before the script runs out of fuel, None for no limit
+    fuel: Option<u64>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    steps: u64,
+    interrupt: Arc<AtomicBool>,
+    //Bytes allocated by scripts and the most they may allocate, see set_memory_limit
+    memory: usize,
+    memory_limit: Option<usize>,
+    capabilities: Capabilities,
+    //The directory relative paths in scripts are resolved against, the process's if None
+    working_dir: Option<PathBuf>,
+    //The backtrace where the error being raised came from, taken when it's caught
+    error_trace: Option<Vec<Symbol>>,
 }
 
 impl Interpreter {
-    fn new(env: Enviroment) -> Self {
-        Interpreter { env }
+    pub fn new(mut env: Enviroment) -> Self {
+        stdlib::load(&mut env);
+        Interpreter {
+            env,
+            frame: None,
+            backend: Backend::TreeWalker,
+            compiled: HashMap::new(),
+            backtrace: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            timeout: None,
+            deadline: None,
+            steps: 0,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            memory: 0,
+            memory_limit: None,
+            capabilities: Capabilities::pure(),
+            working_dir: None,
+            error_trace: None,
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    //Runs a script with the selected backend, `eval` always walks the tree. The timeout
+    //starts when the script does, and an interrupt sent while no script was running is
+    //forgotten.
+    pub fn run(&mut self, ast: &AST) -> Result<Object> {
+        self.error_trace = None;
+        self.interrupt.store(false, Ordering::Relaxed);
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let result = match self.backend {
+            Backend::TreeWalker => self.eval(ast),
+            Backend::Bytecode => vm::run(self, ast),
+        };
+        self.deadline = None;
+        match result {
+            Err(Error::Return(value)) => Ok(value),
+            result => result,
+        }
+    }
+
+    //Limits the number of steps scripts can take, each node evaluated by the tree walker
+    //or instruction run by the vm is a step. The fuel isn't refilled between scripts.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    //Limits the wall clock time each call to `run` can take
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupt.clone())
+    }
+
+    //Scripts can only compute until they are given capabilities
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    //Scripts start in the process's directory, `cd` only changes the interpreter's so the
+    //host and other interpreters aren't affected
+    pub fn set_working_dir(&mut self, dir: PathBuf) {
+        self.working_dir = Some(dir);
+    }
+
+    pub fn working_dir(&self) -> Result<PathBuf> {
+        match &self.working_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => env::current_dir().map_err(|e| Error::IOError(e.to_string())),
+        }
+    }
+
+    //Resolves a path from a script against the working directory and checks it can be read
+    pub(crate) fn check_read(&self, path: &Path) -> Result<PathBuf> {
+        self.capabilities
+            .check_read(&self.working_dir()?.join(path))
+    }
+
+    pub(crate) fn check_write(&self, path: &Path) -> Result<PathBuf> {
+        self.capabilities
+            .check_write(&self.working_dir()?.join(path))
+    }
+
+    pub(crate) fn check_remove(&self, path: &Path) -> Result<PathBuf> {
+        self.capabilities
+            .check_remove(&self.working_dir()?.join(path))
+    }
+
+    //Limits the memory scripts can allocate for strings and arrays. Values aren't
+    //handed back when they are dropped, so this bounds everything a script creates
+    //rather than what it keeps alive. Call reset_memory_usage to start counting again.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.memory_limit = bytes;
+    }
+
+    //The approximate number of bytes allocated by scripts so far
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    pub fn reset_memory_usage(&mut self) {
+        self.memory = 0;
+    }
+
+    //Fails if allocating another `bytes` would go over the limit, for natives to check
+    //before building a large value
+    pub(crate) fn reserve(&self, bytes: usize) -> Result<()> {
+        match self.memory_limit {
+            Some(limit) if self.memory.saturating_add(bytes) > limit => {
+                Err(Error::OutOfMemory(format!(
+                    "allocating {} bytes would exceed the limit of {} bytes",
+                    bytes, limit
+                )))
+            }
+            _ => Ok(()),
+        }
     }
 
-    //Should return a result to an object and remove panic / expects!
-    fn eval(&mut self, node: &AST) -> Object {
+    //Appends the printed form of a value, or its parseable form if quote is set. Each piece
+    //of output is a step and counts towards the memory limit, so printing a huge or
+    //deeply shared value stops at the limits set by the host.
+    pub(crate) fn print_to(
+        &mut self,
+        out: &mut String,
+        object: &Object,
+        quote: bool,
+    ) -> Result<()> {
+        let mut writer = LimitedWriter {
+            interpreter: self,
+            out,
+            error: None,
+        };
+        match object.write_to(&mut writer, quote) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(writer
+                .error
+                .expect("Only the interpreter's limits stop the printer")),
+        }
+    }
+
+    pub(crate) fn print(&mut self, object: &Object, quote: bool) -> Result<String> {
+        let mut s = String::new();
+        self.print_to(&mut s, object, quote)?;
+        Ok(s)
+    }
+
+    //Counts a value that has just been created towards the memory used by scripts
+    pub(crate) fn allocate(&mut self, object: Object) -> Result<Object> {
+        let bytes = allocated_size(&object);
+        self.reserve(bytes)?;
+        self.memory += bytes;
+        Ok(object)
+    }
+
+    //Called before every step, fails once a limit set by the host has been reached
+    pub(crate) fn step(&mut self) -> Result<()> {
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                return Err(Error::OutOfFuel("the script ran out of fuel".to_string()));
+            }
+            self.fuel = Some(fuel - 1);
+        }
+        self.check_interrupt()?;
+        self.steps = self.steps.wrapping_add(1);
+        if self.steps.is_multiple_of(DEADLINE_INTERVAL) {
+            self.check_deadline()?;
+        }
+        Ok(())
+    }
+
+    //For natives that wait on something outside the interpreter, fails if the script has
+    //been interrupted or has run out of time without taking a step
+    pub(crate) fn check_waiting(&self) -> Result<()> {
+        self.check_interrupt()?;
+        self.check_deadline()
+    }
+
+    fn check_interrupt(&self) -> Result<()> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            return Err(Error::Interrupted("the script was interrupted".to_string()));
+        }
+        Ok(())
+    }
+
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Error::Timeout(format!(
+                "the script took longer than {:?}",
+                self.timeout.unwrap_or_default()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn eval(&mut self, node: &AST) -> Result<Object> {
+        //Each scripted call recurses here, so the stack is grown onto the heap when it runs
+        //low and only the call depth limit decides how deep a script can go
+        let result = atom::grow_stack(|| self.eval_node(node));
+        if let Err(error) = &result {
+            self.record_trace(error);
+        }
+        result
+    }
+
+    //Remembers where an error was raised, the first call for an error is the innermost
+    pub(crate) fn record_trace(&mut self, error: &Error) {
+        if self.error_trace.is_none() && error.is_catchable() {
+            self.error_trace = Some(self.backtrace.clone());
+        }
+    }
+
+    //The value a catch block receives for an error. Errors raised by the interpreter or
+    //error() become error objects, anything else that was thrown is given back as is.
+    pub(crate) fn catch(&mut self, error: Error) -> Object {
+        let trace = self.error_trace.take().unwrap_or_default();
+        match error {
+            Error::Thrown(object) => object,
+            error => Object::Error(Rc::new(ErrorValue { error, trace })),
+        }
+    }
+
+    //The error raised by throwing a value, rethrowing a caught error keeps where it was
+    //first raised
+    pub(crate) fn throw(&mut self, object: Object) -> Error {
+        match object {
+            Object::Error(value) => {
+                if value.error.is_catchable() {
+                    self.error_trace = Some(value.trace.clone());
+                }
+                value.error.clone()
+            }
+            object => Error::Thrown(object),
+        }
+    }
+
+    fn eval_node(&mut self, node: &AST) -> Result<Object> {
+        self.step()?;
         match node {
             AST::Node(node) => match &node.head {
-                Atom::Plus | Atom::Minus | Atom::Divide | Atom::Multiply | Atom::Power => {
-                    let right = self.eval(&node.tail[0]);
-                    let left = self.eval(&node.tail[1]);
-                    eval_math(&node.head, &left, &right)
+                Atom::Minus if node.tail.len() == 1 => eval_negate(&self.eval(&node.tail[0])?),
+                Atom::Plus
+                | Atom::Minus
+                | Atom::Divide
+                | Atom::Multiply
+                | Atom::Power
+                | Atom::GreaterThan
+                | Atom::LessThan
+                | Atom::Equal
+                | Atom::NotEqual => {
+                    let left = self.eval(&node.tail[0])?;
+                    let right = self.eval(&node.tail[1])?;
+                    self.eval_operator(&node.head, &left, &right)
                 }
 
                 Atom::Assign => {
                     let key = &node.tail[0];
-                    let value = self.eval(&node.tail[1]);
+                    let mut value = self.eval(&node.tail[1])?;
+
+                    //Defining a function adds a method to a function of the same name
+                    if let (AST::Atom(Atom::Function(_)), Object::Function(method)) =
+                        (&node.tail[1], &value)
+                    {
+                        let existing = match key {
+                            AST::Atom(Atom::Symbol(k)) => self.env.get_symbol(*k).cloned(),
+                            AST::Atom(Atom::Local(local)) => {
+                                self.frame().get(local.depth, local.slot)
+                            }
+                            _ => None,
+                        };
+                        value = define_method(existing, method.clone());
+                    }
 
                     let varname = match key {
                         AST::Atom(Atom::Symbol(k)) => {
-                            self.env.insert(&k, value);
-                            k
+                            self.env.insert_symbol(*k, value);
+                            *k
+                        }
+                        AST::Atom(Atom::Local(local)) => {
+                            self.frame().set(local.depth, local.slot, value);
+                            local.name
+                        }
+                        //Assigning to an index evaluates to the value assigned
+                        AST::Node(Node {
+                            head: Atom::Index,
+                            tail,
+                            ..
+                        }) => {
+                            let target = self.eval(&tail[0])?;
+                            let index = self.eval(&tail[1])?;
+                            eval_set_index(self, &target, &index, value.clone())?;
+                            return Ok(value);
                         }
-                        _ => panic!("Invalid key {:?}", key),
+                        AST::Node(Node {
+                            head: Atom::Field(field),
+                            tail,
+                            ..
+                        }) => {
+                            let target = self.eval(&tail[0])?;
+                            eval_set_field(&target, *field, value.clone())?;
+                            return Ok(value);
+                        }
+                        key => return Err(Error::TypeError(cannot_assign(key))),
                     };
-                    Object::Symbol(varname.to_string())
+                    Ok(Object::Symbol(varname))
                 }
 
-                Atom::GreaterThan => {
-                    let a = self.eval(&node.tail[0]);
-                    let b = self.eval(&node.tail[1]);
-                    eval_gt(&a, &b)
-                }
                 Atom::Block => {
-                    let iter = node.tail.iter();
-                    let n = iter.len() - 1;
+                    let mut result = Object::Nothing;
+                    for ast in node.tail.iter() {
+                        result = self.eval(ast)?;
+                    }
+                    Ok(result)
+                }
+                Atom::If | Atom::ElseIf => {
+                    match expect_bool(self.eval(&node.tail[0])?, "if statement")? {
+                        true => self.eval(&node.tail[1]),
+                        false => self.eval(&node.tail[2]),
+                    }
+                }
+                Atom::Array => {
+                    let items = self.eval_all(&node.tail)?;
+                    self.allocate(Object::array(items))
+                }
+                Atom::Interpolate => {
+                    let mut s = String::new();
+                    for part in node.tail.iter() {
+                        let part = self.eval(part)?;
+                        self.print_to(&mut s, &part, false)?;
+                    }
+                    self.allocate(Object::String(s))
+                }
+                Atom::Try => match self.eval(&node.tail[0]) {
+                    Err(error) if error.is_catchable() => {
+                        let caught = self.catch(error);
+                        match &node.tail[1] {
+                            //A global only holds the error during the catch block, so
+                            //catch e doesn't replace Euler's number for the rest of the
+                            //script. Like a finally block it isn't put back after a limit.
+                            AST::Atom(Atom::Symbol(k)) => {
+                                let previous = self.env.replace_symbol(*k, Some(caught));
+                                let result = self.eval(&node.tail[2]);
+                                if !result.as_ref().is_err_and(Error::is_limit) {
+                                    self.env.replace_symbol(*k, previous);
+                                }
+                                return result;
+                            }
+                            AST::Atom(Atom::Local(local)) => {
+                                self.frame().set(local.depth, local.slot, caught)
+                            }
+                            _ => (),
+                        }
+                        self.eval(&node.tail[2])
+                    }
+                    result => result,
+                },
+                //The value of the block is kept unless the finally block raises an error
+                Atom::Finally => {
+                    let result = self.eval(&node.tail[0]);
+                    if let Err(error) = &result {
+                        if error.is_limit() {
+                            return result;
+                        }
+                    }
+                    let trace = self.error_trace.take();
+                    self.eval(&node.tail[1])?;
+                    self.error_trace = trace;
+                    result
+                }
+                Atom::Return => Err(Error::Return(self.eval(&node.tail[0])?)),
+                Atom::While => {
+                    while expect_bool(self.eval(&node.tail[0])?, "while loop")? {
+                        self.eval(&node.tail[1])?;
+                    }
+                    Ok(Object::Nothing)
+                }
+                Atom::Index => {
+                    let target = self.eval(&node.tail[0])?;
+                    let index = self.eval(&node.tail[1])?;
+                    eval_index(self, &target, &index)
+                }
+                Atom::Field(field) => eval_field(&self.eval(&node.tail[0])?, *field),
+                Atom::Call => {
+                    let function = self.eval(&node.tail[0])?;
+                    let args = self.eval_all(&node.tail[1..])?;
+                    self.call(&function, &args)
+                }
+                op => Err(Error::TypeError(cannot_evaluate(op))),
+            },
+            AST::Atom(atom) => match atom {
+                Atom::Float(value) => Ok(Object::Float(*value)),
+                Atom::String(value) => Ok(Object::String(value.clone())),
+                Atom::Symbol(key) => match self.env.get_symbol(*key) {
+                    Some(object) => Ok(object.clone()),
+                    None => Err(Error::UndefinedVariable(key.to_string())),
+                },
+                Atom::Local(local) => match self.frame().get(local.depth, local.slot) {
+                    Some(object) => Ok(object),
+                    None => Err(Error::UndefinedVariable(local.name.to_string())),
+                },
+                Atom::Function(function) => Ok(Object::Function(Closure {
+                    function: function.clone(),
+                    frame: self.frame.clone(),
+                })),
+                Atom::Struct(definition) => Ok(Object::Type(Type::Struct(definition.clone()))),
+                Atom::False => Ok(Object::Bool(false)),
+                Atom::True => Ok(Object::Bool(true)),
+                Atom::Nothing => Ok(Object::Nothing),
+                atom => Err(Error::TypeError(cannot_evaluate(atom))),
+            },
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Object> {
+        self.env.get(name)
+    }
+
+    pub fn call(&mut self, function: &Object, args: &[Object]) -> Result<Object> {
+        match function {
+            Object::Builtin(builtin) => {
+                let result = (builtin.func)(self, args)?;
+                self.allocate(result)
+            }
+            Object::Type(ty) => {
+                let value = ty.construct(args)?;
+                self.allocate(value)
+            }
+            Object::Function(_) | Object::Generic(_) if self.backend == Backend::Bytecode => {
+                let closure = self.method(function, args)?;
+                vm::call(self, &closure, args)
+            }
+            Object::Function(_) | Object::Generic(_) => {
+                let mut closure = self.method(function, args)?;
+                self.enter(closure.function.name)?;
+                let caller = self.frame.clone();
+                let mut args = args.to_vec();
 
-                    for (i, ast) in iter.enumerate() {
-                        if i == n {
-                            return self.eval(ast);
+                //Tail calls reuse this loop rather than recursing
+                let result = loop {
+                    let tail = match new_frame(&closure, &args) {
+                        Ok(frame) => {
+                            self.frame = Some(frame);
+                            self.eval_tail(&closure.function.body)
+                        }
+                        Err(error) => Err(error),
+                    };
+                    match tail {
+                        Ok(Tail::Value(value)) => break Ok(value),
+                        Ok(Tail::Call(next, next_args)) => {
+                            self.replace_call(next.function.name);
+                            closure = next;
+                            args = next_args;
                         }
-                        self.eval(ast);
+                        Err(Error::Return(value)) => break Ok(value),
+                        //Calls in tail position don't go through eval
+                        Err(error) => {
+                            self.record_trace(&error);
+                            break Err(error);
+                        }
+                    }
+                };
+                self.frame = caller;
+                self.backtrace.pop();
+                result
+            }
+            o => Err(Error::TypeError(format!("{} is not callable", o.repr()))),
+        }
+    }
+
+    //The scripted function a call runs. A generic function runs its most specific method
+    //for the types of the arguments, other functions check any annotated parameters.
+    pub(crate) fn method(&self, function: &Object, args: &[Object]) -> Result<Closure> {
+        match function {
+            //Calls with the wrong number of arguments fail when the frame is made
+            Object::Function(closure)
+                if args.len() == closure.function.params.len()
+                    && !self.applicable(closure, args)? =>
+            {
+                Err(no_method(
+                    closure.function.name,
+                    args,
+                    slice::from_ref(closure),
+                ))
+            }
+            Object::Function(closure) => Ok(closure.clone()),
+            Object::Generic(generic) => {
+                let methods = generic.methods.borrow();
+                let mut candidates = vec![];
+                for method in methods.iter() {
+                    if self.applicable(method, args)? {
+                        candidates.push((method, self.signature(method)?));
                     }
+                }
+
+                //The method whose parameter types are all subtypes of every other's
+                let best = candidates.iter().find(|(_, a)| {
+                    candidates
+                        .iter()
+                        .all(|(_, b)| a.iter().zip(b).all(|(a, b)| a.is_subtype(b)))
+                });
+                match best {
+                    Some((method, _)) => Ok((*method).clone()),
+                    None if candidates.is_empty() => Err(no_method(generic.name, args, &methods)),
+                    None => Err(Error::MethodError(format!(
+                        "{} is ambiguous, the candidates are {}",
+                        format_call(generic.name, args),
+                        format_methods(candidates.iter().map(|(method, _)| *method))
+                    ))),
+                }
+            }
+            o => Err(Error::TypeError(format!("{} is not callable", o.repr()))),
+        }
+    }
 
-                    panic!("An error occured excuting a block");
+    fn applicable(&self, method: &Closure, args: &[Object]) -> Result<bool> {
+        let types = &method.function.types;
+        if types.len() != args.len() {
+            return Ok(false);
+        }
+        for (ty, arg) in types.iter().zip(args) {
+            if let Some(name) = ty {
+                if !self.lookup_type(*name)?.isa(arg) {
+                    return Ok(false);
                 }
-                Atom::If | Atom::ElseIf => match self.eval(&node.tail[0]) {
-                    Object::Bool(true) => self.eval(&node.tail[1]),
-                    Object::Bool(false) => self.eval(&node.tail[2]),
-                    o => panic!("Non boolean value given to if statement {:?}", o),
+            }
+        }
+        Ok(true)
+    }
+
+    //The types of the parameters, those without an annotation are Any
+    fn signature(&self, method: &Closure) -> Result<Vec<Type>> {
+        method
+            .function
+            .types
+            .iter()
+            .map(|ty| match ty {
+                Some(name) => self.lookup_type(*name),
+                None => Ok(Type::Any),
+            })
+            .collect()
+    }
+
+    fn lookup_type(&self, name: Symbol) -> Result<Type> {
+        match self.env.get_symbol(name) {
+            Some(Object::Type(ty)) => Ok(ty.clone()),
+            Some(o) => Err(Error::TypeError(format!(
+                "{} is used as a type but is {}",
+                name,
+                o.repr()
+            ))),
+            None => Err(Error::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    //Operators on structs call the method defined for the operator, such as
+    //function +(a::Vec2, b::Vec2). a > b calls <(b, a) and a != b negates ==, which
+    //compares the fields when no method matches.
+    pub(crate) fn eval_operator(&mut self, atom: &Atom, a: &Object, b: &Object) -> Result<Object> {
+        if let Some(function) = self.overload(atom, a, b) {
+            let args = [a.clone(), b.clone()];
+            match atom {
+                Atom::Equal | Atom::NotEqual => match self.method(&function, &args) {
+                    Ok(method) => {
+                        let result = self.call(&Object::Function(method), &args)?;
+                        let equal = expect_bool(result, "==")?;
+                        return Ok(Object::Bool(equal == (*atom == Atom::Equal)));
+                    }
+                    Err(Error::MethodError(_)) => (),
+                    Err(error) => return Err(error),
                 },
-                Atom::Equal => Object::Bool(self.eval(&node.tail[0]) == self.eval(&node.tail[1])),
-                Atom::NotEqual => {
-                    Object::Bool(self.eval(&node.tail[0]) != self.eval(&node.tail[1]))
+                Atom::GreaterThan => return self.call(&function, &[b.clone(), a.clone()]),
+                _ => return self.call(&function, &args),
+            }
+        }
+
+        match atom {
+            Atom::Equal => Ok(Object::Bool(a == b)),
+            Atom::NotEqual => Ok(Object::Bool(a != b)),
+            Atom::GreaterThan => eval_gt(a, b),
+            Atom::LessThan => eval_gt(b, a),
+            _ => eval_math(atom, a, b),
+        }
+    }
+
+    //The function defined for an operator if either operand is a struct
+    fn overload(&self, atom: &Atom, a: &Object, b: &Object) -> Option<Object> {
+        if !matches!(a, Object::Struct(_)) && !matches!(b, Object::Struct(_)) {
+            return None;
+        }
+        let name = match atom {
+            Atom::Plus => "+",
+            Atom::Minus => "-",
+            Atom::Multiply => "*",
+            Atom::Divide => "/",
+            Atom::Power => "^",
+            Atom::Equal | Atom::NotEqual => "==",
+            Atom::GreaterThan | Atom::LessThan => "<",
+            _ => return None,
+        };
+        self.env.get_symbol(Symbol::intern(name)).cloned()
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    //Records a call to a scripted function, failing once the call depth limit is reached
+    pub(crate) fn enter(&mut self, name: Symbol) -> Result<()> {
+        if self.backtrace.len() >= self.max_depth {
+            return Err(Error::StackOverflow(format!(
+                "maximum call depth of {} exceeded\n{}",
+                self.max_depth,
+                self.format_backtrace()
+            )));
+        }
+        self.backtrace.push(name);
+        Ok(())
+    }
+
+    //A tail call takes the place of the function making it
+    pub(crate) fn replace_call(&mut self, name: Symbol) {
+        if let Some(last) = self.backtrace.last_mut() {
+            *last = name;
+        }
+    }
+
+    //The functions being called, innermost first, with runs of recursion collapsed
+    fn format_backtrace(&self) -> String {
+        const MAX_LINES: usize = 20;
+        let mut lines = vec![];
+        let mut calls = self.backtrace.iter().rev().peekable();
+        while let Some(name) = calls.next() {
+            let mut repeats = 1;
+            while calls.peek() == Some(&name) {
+                calls.next();
+                repeats += 1;
+            }
+            if repeats == 1 {
+                lines.push(format!("  in {}", name));
+            } else {
+                lines.push(format!("  in {} (repeated {} times)", name, repeats));
+            }
+        }
+        if lines.len() > MAX_LINES {
+            let omitted = lines.len() - MAX_LINES;
+            lines.truncate(MAX_LINES);
+            lines.push(format!("  ... {} more", omitted));
+        }
+        lines.join("\n")
+    }
+
+    //Evaluates an expression in tail position of a function body, returning a call to a
+    //scripted function instead of making it so the caller can reuse its frame
+    fn eval_tail(&mut self, ast: &AST) -> Result<Tail> {
+        if let AST::Node(node) = ast {
+            match node.head {
+                Atom::Block => {
+                    if let Some((last, statements)) = node.tail.split_last() {
+                        for statement in statements.iter() {
+                            self.eval(statement)?;
+                        }
+                        return self.eval_tail(last);
+                    }
                 }
-                op => panic!("Unknown operation {:?}", op),
-            },
-            AST::Atom(atom) => match atom {
-                Atom::Float(value) => Object::Float(*value),
-                Atom::Symbol(key) => {
-                    let object = self
-                        .env
-                        .get(key)
-                        .expect(&format!("Object with name {:? } doens't exists", key))
-                        .clone();
-                    //Here actually just need to return a string that is the graphical representation of the object...
-                    object
+                Atom::If | Atom::ElseIf => {
+                    let branch = match expect_bool(self.eval(&node.tail[0])?, "if statement")? {
+                        true => &node.tail[1],
+                        false => &node.tail[2],
+                    };
+                    return self.eval_tail(branch);
                 }
-                Atom::False => Object::Bool(false),
-                Atom::True => Object::Bool(true),
-                Atom::Nothing => Object::Nothing,
-                atom => panic!("Cannot interpret this atom {:?}", atom),
-            },
+                //Returning a call from a function is a tail call
+                Atom::Return => return self.eval_tail(&node.tail[0]),
+                Atom::Call => {
+                    let function = self.eval(&node.tail[0])?;
+                    let args = self.eval_all(&node.tail[1..])?;
+                    return match function {
+                        Object::Function(_) | Object::Generic(_)
+                            if self.backend == Backend::TreeWalker =>
+                        {
+                            Ok(Tail::Call(self.method(&function, &args)?, args))
+                        }
+                        function => self.call(&function, &args).map(Tail::Value),
+                    };
+                }
+                _ => (),
+            }
         }
+        self.eval(ast).map(Tail::Value)
+    }
+
+    //Locals are only resolved inside function bodies, which always run in a frame
+    fn frame(&self) -> &Frame {
+        self.frame
+            .as_ref()
+            .expect("Local variables are only used inside a function call")
     }
+
+    fn eval_all(&mut self, nodes: &[AST]) -> Result<Vec<Object>> {
+        nodes.iter().map(|node| self.eval(node)).collect()
+    }
+}
+
+enum Tail {
+    Value(Object),
+    Call(Closure, Vec<Object>),
 }
 
-fn eval_math(atom: &Atom, x: &Object, y: &Object) -> Object {
+pub(crate) fn expect_bool(condition: Object, statement: &str) -> Result<bool> {
+    match condition {
+        Object::Bool(b) => Ok(b),
+        o => Err(Error::TypeError(format!(
+            "Non boolean value given to {} {}",
+            statement,
+            o.repr()
+        ))),
+    }
+}
+
+//Checks the arguments and sets up the frame for a call to a scripted function
+pub(crate) fn new_frame(closure: &Closure, args: &[Object]) -> Result<Rc<Frame>> {
+    let function = &closure.function;
+    if args.len() != function.params.len() {
+        return Err(Error::ArgumentError(format!(
+            "{} expects {} argument(s) but was given {}",
+            function.name,
+            function.params.len(),
+            args.len()
+        )));
+    }
+
+    let frame = Frame::new(function.slots, closure.frame.clone());
+    for (slot, arg) in args.iter().enumerate() {
+        frame.set(0, slot, arg.clone());
+    }
+    Ok(Rc::new(frame))
+}
+
+//Defining a function again with the same name adds a method to it, replacing the
+//method with the same parameter types if there is one
+pub(crate) fn define_method(existing: Option<Object>, method: Closure) -> Object {
+    let name = method.function.name;
+    let same = |other: &Closure| other.function.types == method.function.types;
+    match existing {
+        Some(Object::Generic(generic)) if generic.name == name => {
+            {
+                let mut methods = generic.methods.borrow_mut();
+                match methods.iter().position(same) {
+                    Some(i) => methods[i] = method,
+                    None => methods.push(method),
+                }
+            }
+            Object::Generic(generic)
+        }
+        Some(Object::Function(closure)) if closure.function.name == name && !same(&closure) => {
+            Object::Generic(Rc::new(Generic {
+                name,
+                methods: RefCell::new(vec![closure, method]),
+            }))
+        }
+        _ => Object::Function(method),
+    }
+}
+
+struct LimitedWriter<'a> {
+    interpreter: &'a mut Interpreter,
+    out: &'a mut String,
+    //Why the interpreter stopped the output, fmt::Error can't say
+    error: Option<Error>,
+}
+
+impl<'a> fmt::Write for LimitedWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = self.out.len().saturating_add(s.len());
+        match self
+            .interpreter
+            .step()
+            .and_then(|_| self.interpreter.reserve(len))
+        {
+            Ok(()) => {
+                self.out.push_str(s);
+                Ok(())
+            }
+            Err(error) => {
+                self.error = Some(error);
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+//The parser accepts any expression on the left of =, such as 1 = 2 or f(x) = 2
+pub(crate) fn cannot_assign(key: &AST) -> String {
+    match key {
+        AST::Atom(atom) => format!("Cannot assign to {:?}", atom),
+        AST::Node(node) => format!("Cannot assign to a {:?} expression", node.head),
+    }
+}
+
+pub(crate) fn cannot_evaluate(atom: &Atom) -> String {
+    format!("Cannot evaluate {:?}", atom)
+}
+
+fn no_method(name: Symbol, args: &[Object], methods: &[Closure]) -> Error {
+    Error::MethodError(format!(
+        "no method matching {}, the candidates are {}",
+        format_call(name, args),
+        format_methods(methods.iter())
+    ))
+}
+
+//f(Float, String)
+fn format_call(name: Symbol, args: &[Object]) -> String {
+    let types: Vec<String> = args.iter().map(|arg| Type::of(arg).to_string()).collect();
+    format!("{}({})", name, types.join(", "))
+}
+
+fn format_methods<'a>(methods: impl Iterator<Item = &'a Closure>) -> String {
+    let signatures: Vec<String> = methods.map(|m| m.function.signature()).collect();
+    signatures.join(", ")
+}
+
+//Caught errors also have fields, their stack trace is innermost first
+pub(crate) fn eval_field(target: &Object, field: Symbol) -> Result<Object> {
+    match target {
+        Object::Struct(instance) => match field_slot(instance, field) {
+            Some(i) => Ok(instance.fields.borrow()[i].clone()),
+            None => Err(no_field(target, field)),
+        },
+        Object::Error(value) => match &*field.as_str() {
+            "kind" => Ok(Object::String(value.error.kind().to_string())),
+            "message" => Ok(Object::String(value.error.message())),
+            "stacktrace" => Ok(Object::array(
+                value
+                    .trace
+                    .iter()
+                    .rev()
+                    .map(|name| Object::String(name.to_string()))
+                    .collect(),
+            )),
+            _ => Err(no_field(target, field)),
+        },
+        o => Err(no_field(o, field)),
+    }
+}
+
+pub(crate) fn eval_set_field(target: &Object, field: Symbol, value: Object) -> Result<()> {
+    match target {
+        Object::Struct(instance) if !instance.ty.mutable => Err(Error::TypeError(format!(
+            "Cannot assign to the field {} of {} as {} is immutable",
+            field,
+            target.repr(),
+            instance.ty.name
+        ))),
+        Object::Struct(instance) => match field_slot(instance, field) {
+            Some(i) => {
+                instance.fields.borrow_mut()[i] = value;
+                Ok(())
+            }
+            None => Err(no_field(target, field)),
+        },
+        o => Err(Error::TypeError(format!(
+            "Cannot assign to the field {} of {}",
+            field,
+            o.repr()
+        ))),
+    }
+}
+
+fn field_slot(instance: &Instance, field: Symbol) -> Option<usize> {
+    instance.ty.fields.iter().position(|f| *f == field)
+}
+
+fn no_field(target: &Object, field: Symbol) -> Error {
+    Error::TypeError(format!("{} has no field {}", target.repr(), field))
+}
+
+pub(crate) fn eval_negate(x: &Object) -> Result<Object> {
+    match x {
+        Object::Float(x) => Ok(Object::Float(-x)),
+        o => Err(Error::TypeError(format!("Cannot negate {}", o.repr()))),
+    }
+}
+
+//The approximate heap size of a new value. The strings in an array are counted with it
+//as builtins build arrays of new strings, nested arrays were counted when they were made.
+fn allocated_size(object: &Object) -> usize {
+    match object {
+        Object::String(s) => s.len(),
+        Object::Array(items) => {
+            let items = items.borrow();
+            let strings: usize = items
+                .iter()
+                .map(|item| match item {
+                    Object::String(s) => s.len(),
+                    _ => 0,
+                })
+                .sum();
+            mem::size_of::<RefCell<Vec<Object>>>()
+                + items.len() * mem::size_of::<Object>()
+                + strings
+        }
+        Object::Struct(instance) => {
+            mem::size_of::<Instance>() + instance.fields.borrow().len() * mem::size_of::<Object>()
+        }
+        _ => 0,
+    }
+}
+
+pub(crate) fn eval_math(atom: &Atom, x: &Object, y: &Object) -> Result<Object> {
     let (x, y) = match (x, y) {
         (Object::Float(x), Object::Float(y)) => (x, y),
-        (x, y) => panic!("Cannot evaluate maths with {:?} {:?}", x, y),
+        (x, y) => {
+            return Err(Error::TypeError(format!(
+                "Cannot evaluate maths with {} {}",
+                x.repr(),
+                y.repr()
+            )))
+        }
     };
 
-    match atom {
-        Atom::Plus => Object::Float(x + y),
-        Atom::Minus => Object::Float(x - y),
-        Atom::Divide => Object::Float(x / y),
-        Atom::Multiply => Object::Float(x * y),
-        Atom::Power => Object::Float(y.powf(*x)),
+    let result = match atom {
+        Atom::Plus => x + y,
+        Atom::Minus => x - y,
+        Atom::Divide => x / y,
+        Atom::Multiply => x * y,
+        Atom::Power => x.powf(*y),
         _ => panic!("Atom {:?} has math no operation implemented", atom),
+    };
+    Ok(Object::Float(result))
+}
+
+//Indexes are 1 based, strings are indexed by character rather than by byte. Indexing a
+//struct calls getindex(target, index).
+pub(crate) fn eval_index(
+    interpreter: &mut Interpreter,
+    target: &Object,
+    index: &Object,
+) -> Result<Object> {
+    if let Object::Env = target {
+        let key = env_key(interpreter, index)?;
+        return env::var(key)
+            .map(Object::String)
+            .map_err(|_| Error::KeyError(format!("the environment variable {} is not set", key)));
+    }
+    if let Some(function) = index_method(interpreter, target, "getindex") {
+        return interpreter.call(&function, &[target.clone(), index.clone()]);
+    }
+
+    let i = array_index(target, index)?;
+    match target {
+        Object::Array(items) => items
+            .borrow()
+            .get(i)
+            .cloned()
+            .ok_or_else(|| out_of_bounds(target, i)),
+        Object::String(s) => s
+            .chars()
+            .nth(i)
+            .map(|c| Object::String(c.to_string()))
+            .ok_or_else(|| out_of_bounds(target, i)),
+        o => Err(Error::TypeError(format!("Cannot index into {}", o.repr()))),
     }
 }
 
-fn eval_gt(a: &Object, b: &Object) -> Object {
-    match (a, b) {
-        (Object::Float(x), Object::Float(y)) => Object::Bool(x > y),
-        _ => panic!(
-            "Greater than not implemented for objects of type {:?} and {:?}",
-            a, b
+//Arrays can have their elements replaced but not grown, setting an environment variable
+//to nothing removes it. Assigning to an index of a struct calls setindex!(target, value,
+//index).
+pub(crate) fn eval_set_index(
+    interpreter: &mut Interpreter,
+    target: &Object,
+    index: &Object,
+    value: Object,
+) -> Result<()> {
+    if let Some(function) = index_method(interpreter, target, "setindex!") {
+        interpreter.call(&function, &[target.clone(), value, index.clone()])?;
+        return Ok(());
+    }
+    match target {
+        Object::Array(items) => {
+            let i = array_index(target, index)?;
+            match items.borrow_mut().get_mut(i) {
+                Some(item) => *item = value,
+                None => return Err(out_of_bounds(target, i)),
+            }
+        }
+        Object::Env => {
+            let key = env_key(interpreter, index)?;
+            match value {
+                Object::Nothing => env::remove_var(key),
+                value => {
+                    let value = value.to_string();
+                    if value.contains('\0') {
+                        return Err(Error::ArgumentError(format!(
+                            "The environment variable {} can't be set to a string containing NUL",
+                            key
+                        )));
+                    }
+                    env::set_var(key, value)
+                }
+            }
+        }
+        o => {
+            return Err(Error::TypeError(format!(
+                "Cannot assign to an index of {}",
+                o.repr()
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn index_method(interpreter: &Interpreter, target: &Object, name: &str) -> Option<Object> {
+    match target {
+        Object::Struct(_) => interpreter.get(name).cloned(),
+        _ => None,
+    }
+}
+
+//Indices past the largest integer a float holds exactly can't be in bounds, checking before
+//casting stops them saturating to usize::MAX and being reported as a different index
+const MAX_INDEX: f64 = 9007199254740992.0;
+
+//The 0 based position of a 1 based index
+fn array_index(target: &Object, index: &Object) -> Result<usize> {
+    match index {
+        Object::Float(i) if i.fract() == 0.0 && (1.0..=MAX_INDEX).contains(i) => {
+            Ok(*i as usize - 1)
+        }
+        Object::Float(i) if i.fract() == 0.0 => Err(Error::BoundsError(format!(
+            "Attempt to access {} at index {}",
+            target.repr(),
+            index.repr()
+        ))),
+        o => Err(Error::TypeError(format!("Invalid index {}", o.repr()))),
+    }
+}
+
+fn out_of_bounds(target: &Object, i: usize) -> Error {
+    Error::BoundsError(format!(
+        "Attempt to access {} at index {}",
+        target.repr(),
+        i + 1
+    ))
+}
+
+//Names the OS can't store, which would make std::env panic, are rejected
+fn env_key<'a>(interpreter: &Interpreter, index: &'a Object) -> Result<&'a str> {
+    interpreter.capabilities().check_env()?;
+    match index {
+        Object::String(key) if key.is_empty() || key.contains(&['=', '\0'][..]) => Err(
+            Error::ArgumentError(format!("Invalid environment variable name {:?}", key)),
         ),
+        Object::String(key) => Ok(key),
+        o => Err(Error::TypeError(format!(
+            "Environment variables are named by strings not {}",
+            o.repr()
+        ))),
+    }
+}
+
+pub(crate) fn eval_gt(a: &Object, b: &Object) -> Result<Object> {
+    match (a, b) {
+        (Object::Float(x), Object::Float(y)) => Ok(Object::Bool(x > y)),
+        _ => Err(Error::TypeError(format!(
+            "Greater than not implemented for objects of type {} and {}",
+            a.repr(),
+            b.repr()
+        ))),
     }
 }
 
@@ -115,17 +1262,15 @@ mod tests {
     use super::*;
     use crate::lexer::Lexer;
     use crate::parser::Parser;
-    use crate::tokenizer::tokenize;
+    use crate::symbol::Symbol;
+    use crate::vm::eval_both;
 
     fn setup(s: &str) -> (Interpreter, AST) {
         //Stage 1
-        let tokens = tokenize(s).expect("Setup failed to tokenize the string");
-        println!("TOKENS {:?}", tokens);
-        let mut lexer = Lexer::new(tokens);
-        println!("LEXED {:?}", lexer.all());
+        let lexer = Lexer::new(s);
         //Stage 2
         let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
+        let ast = parser.parse().expect("Setup failed to parse the string");
         //Stage 3
         let env = Enviroment::new();
         let interpreter = Interpreter::new(env);
@@ -134,21 +1279,23 @@ mod tests {
 
     fn setup_block(s: &str) -> (Interpreter, AST) {
         //Stage 1
-        let tokens = tokenize(s).expect("Setup failed to tokenize the string");
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new(s);
         //Stage 2
         let mut parser = Parser::new(lexer);
-        let ast = parser.parse_block();
+        let ast = parser
+            .parse_block()
+            .expect("Setup failed to parse the string");
         //Stage 3
         let env = Enviroment::new();
         let interpreter = Interpreter::new(env);
         (interpreter, ast)
     }
 
+    //Every test runs the script on both backends and checks they agree, see vm::eval_both
     #[test]
     fn power() {
         let (mut interpreter, ast) = setup("3 ^ 2 * 10");
-        let result = interpreter.eval(&ast);
+        let result = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Float(90.0);
         assert_eq!(result, ans);
     }
@@ -156,7 +1303,7 @@ mod tests {
     #[test]
     fn booleans() {
         let (mut interpreter, ast) = setup("false");
-        let result = interpreter.eval(&ast);
+        let result = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(false);
         assert_eq!(result, ans);
     }
@@ -164,19 +1311,37 @@ mod tests {
     #[test]
     fn assignment() {
         let (mut interpreter, ast) = setup("x = 10");
-        interpreter.eval(&ast);
+        eval_both(&mut interpreter, &ast).unwrap();
         let val = interpreter
             .env
             .get("x")
-            .expect(&format!("No value x in env: {:?}", interpreter.env));
+            .unwrap_or_else(|| panic!("No value x in env: {:?}", interpreter.env));
         let ans = Object::Float(10.0);
         assert_eq!(val, &ans);
     }
 
+    #[test]
+    fn invalid_assignment_targets() {
+        let cases = [
+            ("1 = 2", "Cannot assign to Float(1.0)"),
+            ("f(x) = 2", "Cannot assign to a Call expression"),
+            ("-x = 2", "Cannot assign to a Minus expression"),
+        ];
+        for (s, message) in cases.iter() {
+            let (mut interpreter, ast) = setup(s);
+            let res = eval_both(&mut interpreter, &ast);
+            assert_eq!(res, Err(Error::TypeError(message.to_string())));
+        }
+
+        //The error is only raised if the assignment runs
+        let (mut interpreter, ast) = setup_block("if false then 1 = 2 end");
+        assert_eq!(eval_both(&mut interpreter, &ast), Ok(Object::Nothing));
+    }
+
     #[test]
     fn gt() {
         let (mut interpreter, ast) = setup("5 > 10");
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(false);
         assert_eq!(&res, &ans);
     }
@@ -184,12 +1349,12 @@ mod tests {
     #[test]
     fn equals() {
         let (mut interpreter, ast) = setup("5 == 10");
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(false);
         assert_eq!(&res, &ans);
 
         let (mut interpreter, ast) = setup("5 == 5");
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(true);
         assert_eq!(&res, &ans);
     }
@@ -197,12 +1362,12 @@ mod tests {
     #[test]
     fn not_equals() {
         let (mut interpreter, ast) = setup("5 != 5");
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(false);
         assert_eq!(&res, &ans);
 
         let (mut interpreter, ast) = setup("5 != 10");
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(true);
         assert_eq!(&res, &ans);
     }
@@ -217,7 +1382,7 @@ mod tests {
         ",
         );
 
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         let ans = Object::Bool(true);
         assert_eq!(&res, &ans);
     }
@@ -233,14 +1398,14 @@ mod tests {
         ",
         );
 
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
 
         let val = interpreter
             .env
             .get("y")
-            .expect(&format!("No value y in env: {:?}", interpreter.env));
+            .unwrap_or_else(|| panic!("No value y in env: {:?}", interpreter.env));
         assert_eq!(val, &Object::Float(2.0));
-        assert_eq!(&res, &Object::Symbol("y".to_string()));
+        assert_eq!(&res, &Object::Symbol(Symbol::intern("y")));
     }
 
     #[test]
@@ -256,14 +1421,14 @@ mod tests {
         ",
         );
 
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
 
         let val = interpreter
             .env
             .get("y")
-            .expect(&format!("No value y in env: {:?}", interpreter.env));
+            .unwrap_or_else(|| panic!("No value y in env: {:?}", interpreter.env));
         assert_eq!(val, &Object::Float(10.0));
-        assert_eq!(&res, &Object::Symbol("y".to_string()));
+        assert_eq!(&res, &Object::Symbol(Symbol::intern("y")));
     }
 
     #[test]
@@ -280,7 +1445,7 @@ mod tests {
 
         println!("AST {:?}", ast);
 
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         assert_eq!(&res, &Object::Float(20.0));
     }
 
@@ -298,7 +1463,825 @@ mod tests {
         ",
         );
 
-        let res = interpreter.eval(&ast);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
         assert_eq!(&res, &Object::Float(30.0));
     }
+
+    #[test]
+    fn maths_operand_order() {
+        let (mut interpreter, ast) = setup("10 - 4 / 2");
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(&res, &Object::Float(8.0));
+
+        let (mut interpreter, ast) = setup("-2 ^ 2");
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(&res, &Object::Float(-4.0));
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let (mut interpreter, ast) = setup("x + 1");
+        let res = eval_both(&mut interpreter, &ast);
+        assert_eq!(res, Err(Error::UndefinedVariable("x".to_string())));
+    }
+
+    #[test]
+    fn string_builtin() {
+        let (mut interpreter, ast) = setup(r#"string("x = ", 3, " ", [1.5, "a"], " ", true)"#);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res, Object::String(r#"x = 3 [1.5, "a"] true"#.to_string()));
+    }
+
+    #[test]
+    fn repr_round_trips() {
+        let (mut interpreter, ast) = setup(r#"repr(["tab\t", 0.1, -3, [nothing, false]])"#);
+        let value = match eval_both(&mut interpreter, &ast).unwrap() {
+            Object::String(s) => s,
+            o => panic!("Expected a string from repr but got {:?}", o),
+        };
+        assert_eq!(value, r#"["tab\t", 0.1, -3, [nothing, false]]"#);
+
+        let (mut interpreter, ast) = setup(&value);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), value);
+    }
+
+    #[test]
+    fn interpolation() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        x = 1.5
+        name = "lemur"
+        "$name: $(x * 2) $([x, name]) \$x $("nested $(x + 1)")"
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(
+            res,
+            Object::String(r#"lemur: 3 [1.5, "lemur"] $x nested 2.5"#.to_string())
+        );
+
+        //A literal dollar sign is escaped by repr so the output doesn't interpolate
+        let (mut interpreter, ast) = setup(r#"repr("\$x")"#);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res, Object::String(r#""\$x""#.to_string()));
+    }
+
+    #[test]
+    fn indexing() {
+        let (mut interpreter, ast) = setup(r#"["a", ["b", "αβγ"]][2][2][3]"#);
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res, Object::String("γ".to_string()));
+
+        let (mut interpreter, ast) = setup("[1, 2][3]");
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::BoundsError(_)) => (),
+            res => panic!("Expected a bounds error but got {:?}", res),
+        }
+
+        for (s, index) in [
+            ("[1, 2][1e20]", "100000000000000000000"),
+            (r#""ab"[-1]"#, "-1"),
+        ]
+        .iter()
+        {
+            let (mut interpreter, ast) = setup(s);
+            match eval_both(&mut interpreter, &ast) {
+                Err(Error::BoundsError(msg)) => {
+                    assert!(msg.ends_with(&format!(" at index {}", index)), "{}", msg)
+                }
+                res => panic!("Expected a bounds error but got {:?}", res),
+            }
+        }
+
+        let (mut interpreter, ast) =
+            setup_block("a = [1, [2]]; b = a; function f(x) x[2][1] = 3 end; [f(a), b]");
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[3, [1, [3]]]");
+
+        let (mut interpreter, ast) = setup(r#""abc"[1] = "d""#);
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::TypeError(msg)) => assert_eq!(msg, r#"Cannot assign to an index of "abc""#),
+            res => panic!("Expected a type error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn builtin_arity() {
+        let (mut interpreter, ast) = setup("show(1, 2)");
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn functions_and_recursion() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        function fib(n)
+            if n < 2 then
+                n
+            else
+                fib(n - 1) + fib(n - 2)
+            end
+        end
+        fib(15)
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res, Object::Float(610.0));
+
+        let (mut interpreter, ast) = setup_block(
+            "
+        function f(x) x end
+        f(1, 2)
+        ",
+        );
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn locals_and_globals() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        x = 1
+        scale = 10
+        function f(y)
+            x = y * scale
+            x + 1
+        end
+        [f(2), x]
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[21, 1]");
+        assert_eq!(interpreter.get("y"), None);
+
+        //A local read before it's assigned is undefined even when a global has the name
+        let (mut interpreter, ast) = setup_block(
+            "
+        x = 1
+        function f()
+            y = x
+            x = 2
+        end
+        f()
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast);
+        assert_eq!(res, Err(Error::UndefinedVariable("x".to_string())));
+    }
+
+    #[test]
+    fn closures() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        function counter(step)
+            count = 0
+            function next()
+                count = count + step
+                count
+            end
+            next
+        end
+        a = counter(1)
+        b = counter(10)
+        a()
+        a()
+        b()
+        [a(), b()]
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[3, 20]");
+    }
+
+    #[test]
+    fn try_catch() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        function check(x)
+            if x > 10 then
+                error("too big: ", x)
+            end
+            x
+        end
+        function safe(x)
+            try
+                check(x)
+            catch e
+                e
+            end
+        end
+        a = try 1 + "a" catch e; e end
+        b = try undefined_name catch; "caught" end
+        c = try throw([1, 2]) catch e; e end
+        d = try missing end
+        [safe(5), safe(50), a, b, c, d]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        let ans = r#"[5, ErrorException("too big: 50"), TypeError("Cannot evaluate maths with 1 \"a\""), "caught", [1, 2], nothing]"#;
+        assert_eq!(res.repr(), ans);
+
+        //The caught error remembers where it was raised
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        function check(x) error("x") end
+        function safe(x) try check(x) catch e; e end end
+        safe(50)
+        "#,
+        );
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            interpreter.set_backend(*backend);
+            match interpreter.run(&ast).unwrap() {
+                Object::Error(value) => {
+                    let trace: Vec<String> = value.trace.iter().map(|s| s.to_string()).collect();
+                    assert_eq!(trace, vec!["safe", "check"]);
+                    assert_eq!(value.error.to_string(), "ErrorException: x");
+                }
+                res => panic!("Expected an error but got {:?}", res),
+            }
+            assert!(interpreter.backtrace.is_empty());
+        }
+    }
+
+    #[test]
+    fn global_catch_variables_only_last_for_the_catch_block() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        before = e
+        a = try error("x") catch e; e end
+        b = try try error("y") catch e; error("z") end catch err; err end
+        [a, b, e == before]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(
+            res.repr(),
+            r#"[ErrorException("x"), ErrorException("z"), true]"#
+        );
+
+        let (mut interpreter, ast) = setup_block(r#"try error("x") catch q; q end; q"#);
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::UndefinedVariable(name)) => assert_eq!(name, "q"),
+            res => panic!("Expected q to be undefined but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn finally_and_rethrow() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        log = [0]
+        function f(x)
+            try
+                try
+                    x + 1
+                finally
+                    log[1] = log[1] + 1
+                end
+            catch e
+                throw(e)
+            end
+        end
+        a = f(1)
+        b = try f("a") catch e; e end
+        [a, b, log]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        let ans = r#"[2, TypeError("Cannot evaluate maths with \"a\" 1"), [2]]"#;
+        assert_eq!(res.repr(), ans);
+
+        //Rethrown errors reach the host as they were raised
+        let (mut interpreter, ast) = setup_block(
+            "
+        try
+            undefined_name
+        catch e
+            throw(e)
+        finally
+            1
+        end
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast);
+        assert_eq!(
+            res,
+            Err(Error::UndefinedVariable("undefined_name".to_string()))
+        );
+
+        let (mut interpreter, ast) = setup("throw(1)");
+        let res = eval_both(&mut interpreter, &ast);
+        assert_eq!(res, Err(Error::Thrown(Object::Float(1.0))));
+        assert_eq!(res.unwrap_err().to_string(), "Thrown: 1");
+    }
+
+    #[test]
+    fn early_return() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        function find(items, x)
+            i = 1
+            while true
+                if items[i] == x then
+                    return i
+                end
+                i = i + 1
+            end
+        end
+        function nothing_back() 1; return; 2 end
+        function mid_expression() 1 + length([1, (return 5)]) end
+        cleanups = [0]
+        function guarded(x)
+            try
+                try
+                    return x * 2
+                catch e
+                    return "caught"
+                end
+            finally
+                cleanups[1] = cleanups[1] + 1
+            end
+            "unreachable"
+        end
+        function down(n)
+            if n == 0 then
+                return "done"
+            end
+            return down(n - 1)
+        end
+        [find(["a", "b", "c"], "c"), nothing_back(), 1 + mid_expression(), guarded(4), cleanups, down(50000)]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), r#"[3, nothing, 6, 8, [1], "done"]"#);
+
+        //A return at the top level ends the script
+        let (mut interpreter, ast) = setup_block("x = 1; if x == 1 then return \"early\" end; 2");
+        assert_eq!(
+            eval_both(&mut interpreter, &ast),
+            Ok(Object::String("early".to_string()))
+        );
+    }
+
+    #[test]
+    fn limits_cannot_be_caught() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        ran_finally = false
+        try
+            while true end
+        catch
+            1
+        finally
+            ran_finally = true
+        end
+        ",
+        );
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            interpreter.set_backend(*backend);
+            interpreter.set_fuel(Some(1000));
+            match interpreter.run(&ast) {
+                Err(Error::OutOfFuel(_)) => (),
+                res => panic!("Expected to run out of fuel but got {:?}", res),
+            }
+            assert_eq!(interpreter.get("ran_finally"), Some(&Object::Bool(false)));
+        }
+    }
+
+    #[test]
+    fn while_loop() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        function triangle(n)
+            total = 0
+            while n > 0
+                total = total + n
+                n = n - 1
+            end
+            total
+        end
+        triangle(100)
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res, Object::Float(5050.0));
+
+        let (mut interpreter, ast) = setup("while 1 end");
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::TypeError(_)) => (),
+            res => panic!("Expected a type error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn tail_calls() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        function sum(n, total)
+            if n == 0 then
+                total
+            else
+                sum(n - 1, total + n)
+            end
+        end
+        function even(n) if n == 0 then true else odd(n - 1) end end
+        function odd(n) if n == 0 then false else even(n - 1) end end
+        [sum(50000, 0), even(20001)]
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[1250025000, false]");
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let (mut interpreter, ast) = setup_block(
+            "
+        function f(n) 1 + g(n) end
+        function g(n) 1 + f(n + 1) end
+        function h() 1 + h() end
+        f(1)
+        ",
+        );
+        let msg = match eval_both(&mut interpreter, &ast) {
+            Err(Error::StackOverflow(msg)) => msg,
+            res => panic!("Expected a stack overflow but got {:?}", res),
+        };
+        let lines: Vec<&str> = msg.lines().collect();
+        assert_eq!(lines.len(), 22);
+        assert_eq!(lines[0], "maximum call depth of 10000 exceeded");
+        assert_eq!(lines[1], "  in g");
+        assert_eq!(lines[2], "  in f");
+        assert_eq!(lines[21], "  ... 9980 more");
+
+        //The interpreter is still usable with a different limit afterwards
+        interpreter.set_max_depth(5);
+        let (_, ast) = setup("h()");
+        match interpreter.eval(&ast) {
+            Err(Error::StackOverflow(msg)) => {
+                assert!(msg.ends_with("in h (repeated 5 times)"), "{}", msg)
+            }
+            res => panic!("Expected a stack overflow but got {:?}", res),
+        }
+        let (_, ast) = setup("f");
+        assert!(interpreter.eval(&ast).is_ok());
+        assert!(interpreter.backtrace.is_empty());
+    }
+
+    #[test]
+    fn deeply_nested_scripts() {
+        //Every pass runs on the test thread's small stack
+        let n = crate::parser::MAX_NESTING / 4;
+        let cases = [
+            format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}1", "1 + ".repeat(n)),
+            format!("{}1{}", "[".repeat(n), "]".repeat(n)),
+            format!("{}1{}", "if true then ".repeat(n), " end".repeat(n)),
+            format!("{}f(){}\nf()", "function f() ".repeat(n), " end".repeat(n)),
+        ];
+        for s in cases.iter() {
+            let (mut interpreter, ast) = setup_block(s);
+            assert!(eval_both(&mut interpreter, &ast).is_ok());
+            assert!(crate::checker::check(s).is_ok());
+        }
+    }
+
+    #[test]
+    fn fuel_timeouts_and_interrupts() {
+        let (mut interpreter, ast) = setup_block("while true end");
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            interpreter.set_backend(*backend);
+            interpreter.set_fuel(Some(1000));
+            match interpreter.run(&ast) {
+                Err(Error::OutOfFuel(_)) => (),
+                res => panic!("Expected to run out of fuel but got {:?}", res),
+            }
+            assert_eq!(interpreter.fuel(), Some(0));
+            interpreter.set_fuel(None);
+
+            interpreter.set_timeout(Some(Duration::from_millis(10)));
+            match interpreter.run(&ast) {
+                Err(Error::Timeout(_)) => (),
+                res => panic!("Expected a timeout but got {:?}", res),
+            }
+            interpreter.set_timeout(None);
+        }
+
+        //The handle can be sent to another thread while the script runs
+        let handle = interpreter.interrupt_handle();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.interrupt();
+        });
+        match interpreter.run(&ast) {
+            Err(Error::Interrupted(_)) => (),
+            res => panic!("Expected an interrupt but got {:?}", res),
+        }
+        thread.join().unwrap();
+
+        //An interrupt only stops the script that was running
+        interpreter.set_fuel(Some(100));
+        let (_, ast) = setup("1 + 2");
+        assert_eq!(interpreter.run(&ast), Ok(Object::Float(3.0)));
+        assert!(interpreter.fuel().unwrap() < 100);
+    }
+
+    #[test]
+    fn printing_stops_at_the_limits() {
+        //Printing a shares its elements 2^60 times over
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        a = [1]
+        i = 0
+        while i < 60
+            a = [a, a]
+            i = i + 1
+        end
+        "#,
+        );
+        interpreter.run(&ast).unwrap();
+        let cases = [
+            "string(a)",
+            "repr(a)",
+            "println(a)",
+            r#"join([a], ",")"#,
+            r#""$(a)""#,
+            r#"format("{}", a)"#,
+        ];
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            interpreter.set_backend(*backend);
+            for case in cases.iter() {
+                let (_, ast) = setup(case);
+                interpreter.set_fuel(Some(10_000));
+                match interpreter.run(&ast) {
+                    Err(Error::OutOfFuel(_)) => (),
+                    res => panic!("Expected {} to run out of fuel but got {:?}", case, res),
+                }
+                interpreter.set_fuel(None);
+
+                interpreter.set_memory_limit(Some(100_000));
+                match interpreter.run(&ast) {
+                    Err(Error::OutOfMemory(_)) => (),
+                    res => panic!("Expected {} to run out of memory but got {:?}", case, res),
+                }
+                interpreter.set_memory_limit(None);
+                interpreter.reset_memory_usage();
+
+                interpreter.set_timeout(Some(Duration::from_millis(10)));
+                match interpreter.run(&ast) {
+                    Err(Error::Timeout(_)) => (),
+                    res => panic!("Expected {} to time out but got {:?}", case, res),
+                }
+                interpreter.set_timeout(None);
+            }
+        }
+    }
+
+    #[test]
+    fn memory_limit() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        parts = []
+        while true
+            parts = [parts, "$(1)"]
+        end
+        "#,
+        );
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            interpreter.set_backend(*backend);
+            interpreter.set_memory_limit(Some(10_000));
+            match interpreter.run(&ast) {
+                Err(Error::OutOfMemory(_)) => (),
+                res => panic!("Expected to run out of memory but got {:?}", res),
+            }
+            let usage = interpreter.memory_usage();
+            assert!(usage > 9_000 && usage <= 10_000, "{}", usage);
+            interpreter.reset_memory_usage();
+        }
+
+        //Large values are refused before they are built
+        let (_, ast) = setup(r#"repeat("abc", 1e12)"#);
+        match interpreter.run(&ast) {
+            Err(Error::OutOfMemory(msg)) => assert_eq!(
+                msg,
+                "allocating 3000000000000 bytes would exceed the limit of 10000 bytes"
+            ),
+            res => panic!("Expected to run out of memory but got {:?}", res),
+        }
+        assert_eq!(interpreter.memory_usage(), 0);
+
+        let (_, ast) = setup(r#"split("a b c")"#);
+        assert!(interpreter.run(&ast).is_ok());
+        assert!(interpreter.memory_usage() > 0);
+    }
+
+    #[test]
+    fn structs() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        struct Point
+            x
+            y
+        end
+        mutable struct Counter count end
+        p = Point(1, "a")
+        c = Counter(0)
+        function bump(c) c.count = c.count + 1 end
+        bump(c); bump(c)
+        nested = Point(p, [Point(2, 3)])
+        [p.x, p.y, c, p == Point(1, "a"), p == Point(2, "a"), nested.y[1].x, nested, Point]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        let ans =
+            r#"[1, "a", Counter(2), true, false, 2, Point(Point(1, "a"), [Point(2, 3)]), Point]"#;
+        assert_eq!(res.repr(), ans);
+        assert_eq!(interpreter.get("c").unwrap().to_string(), "Counter(2)");
+
+        let (mut interpreter, ast) = setup_block(
+            "
+        mutable struct Node value; next end
+        a = Node(1, nothing)
+        a.next = a
+        a
+        ",
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "Node(1, Node(...))");
+        if let Object::Struct(instance) = res {
+            instance.fields.borrow_mut().clear();
+        }
+
+        let errors = [
+            ("struct P x end; p = P(1); p.x = 2", "TypeError"),
+            ("struct P x end; P(1).y", "TypeError"),
+            ("struct P x end; P(1, 2)", "ArgumentError"),
+            ("x = 1; x.y", "TypeError"),
+        ];
+        for (s, kind) in errors.iter() {
+            let (mut interpreter, ast) = setup_block(s);
+            match eval_both(&mut interpreter, &ast) {
+                Err(error) => assert_eq!(error.kind(), *kind, "{}", s),
+                res => panic!("Expected {} to fail but got {:?}", s, res),
+            }
+        }
+    }
+
+    #[test]
+    fn error_fields() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        function check(x) error("bad ", x) end
+        function safe(x) try check(x) catch e; e end end
+        e = safe(1)
+        [e.kind, e.message, e.stacktrace]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(
+            res.repr(),
+            r#"["ErrorException", "bad 1", ["check", "safe"]]"#
+        );
+    }
+
+    #[test]
+    fn multiple_dispatch() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        struct Point x; y end
+        function describe(x) "anything" end
+        function describe(x::Float) "float" end
+        function describe(x::String) "string" end
+        function describe(p::Point) "point" end
+        function describe(x, y) "pair" end
+        function describe(x::Float, y::Float) "floats" end
+        function describe(x::String) "a string" end
+        function half(x::Float) x / 2 end
+        function tail(x::Float) describe(x, x) end
+        [describe(1), describe("a"), describe(Point(1, 2)), describe(true), describe(1, "a"),
+         describe(1, 2), half(3), tail(1), describe]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        let ans = r#"["float", "a string", "point", "anything", "pair", "floats", 1.5, "floats", describe]"#;
+        assert_eq!(res.repr(), ans);
+        match interpreter.get("describe") {
+            Some(Object::Generic(generic)) => assert_eq!(generic.methods.borrow().len(), 6),
+            res => panic!("Expected a generic function but got {:?}", res),
+        }
+
+        let errors = [
+            (
+                r#"function f(x::Float) x end; f("a")"#,
+                "no method matching f(String), the candidates are f(x::Float)",
+            ),
+            (
+                "function f(x::Float) x end; function f(x::String) x end; f(true)",
+                "no method matching f(Bool), the candidates are f(x::Float), f(x::String)",
+            ),
+            (
+                "function f(x::Float, y) 1 end; function f(x, y::Float) 2 end; f(1, 2)",
+                "f(Float, Float) is ambiguous, the candidates are f(x::Float, y), f(x, y::Float)",
+            ),
+        ];
+        for (s, msg) in errors.iter() {
+            let (mut interpreter, ast) = setup_block(s);
+            match eval_both(&mut interpreter, &ast) {
+                Err(Error::MethodError(res)) => assert_eq!(res, *msg),
+                res => panic!("Expected a MethodError for {} but got {:?}", s, res),
+            }
+        }
+
+        let (mut interpreter, ast) = setup_block("function f(x::Point) x end; f(1)");
+        match eval_both(&mut interpreter, &ast) {
+            Err(Error::UndefinedVariable(name)) => assert_eq!(name, "Point"),
+            res => panic!("Expected Point to be undefined but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn operator_overloading() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        struct Vec2 x; y end
+        mutable struct Grid cells end
+        function +(a::Vec2, b::Vec2) Vec2(a.x + b.x, a.y + b.y) end
+        function -(a::Vec2, b::Vec2) Vec2(a.x - b.x, a.y - b.y) end
+        function *(a::Vec2, k::Float) Vec2(a.x * k, a.y * k) end
+        function *(k::Float, a::Vec2) a * k end
+        function <(a::Vec2, b::Vec2) a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y end
+        function ==(a::Vec2, b::Vec2) a.x == b.x end
+        function getindex(g::Grid, i) g.cells[i] end
+        function setindex!(g::Grid, value, i) g.cells[i] = value * 10 end
+        a = Vec2(1, 2)
+        b = Vec2(3, 4)
+        g = Grid([1, 2])
+        g[2] = 5
+        [a + b, b - a, 2 * a, a * 3, a < b, a > b, a == Vec2(1, 9), a != Vec2(1, 9),
+         a == b, g[2], Grid(1) == Grid(1), 1 + 2]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        let ans = "[Vec2(4, 6), Vec2(2, 2), Vec2(2, 4), Vec2(3, 6), true, false, true, false, false, 50, true, 3]";
+        assert_eq!(res.repr(), ans);
+
+        let errors = [
+            ("struct V x end; V(1) + V(2)", "TypeError"),
+            (
+                "struct V x end; function +(a::V, b::V) 1 end; V(1) + 2",
+                "MethodError",
+            ),
+            ("struct V x end; V(1)[1]", "TypeError"),
+            (
+                "struct V x end; function ==(a::V, b::V) 1 end; V(1) == V(1)",
+                "TypeError",
+            ),
+        ];
+        for (s, kind) in errors.iter() {
+            let (mut interpreter, ast) = setup_block(s);
+            match eval_both(&mut interpreter, &ast) {
+                Err(error) => assert_eq!(error.kind(), *kind, "{}", s),
+                res => panic!("Expected {} to fail but got {:?}", s, res),
+            }
+        }
+    }
+
+    #[test]
+    fn comparing_cyclic_values() {
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        mutable struct N x end
+        n = N(1); n.x = n
+        m = N(1); m.x = m
+        a = [1, 2]; a[1] = a
+        b = [1, 2]; b[1] = b
+        c = [1, 3]; c[1] = c
+        [n == m, a == b, a == c, a == a, n == N(n)]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[true, true, false, true, true]");
+    }
+
+    #[test]
+    fn deep_overloaded_recursion() {
+        //The vm runs each overloaded call in a nested execute
+        let (mut interpreter, ast) = setup_block(
+            r#"
+        struct N v end
+        function +(a::N, b::N) if a.v > 0 then N(a.v - 1) + b else b end end
+        function getindex(a::N, i) if i > 0 then a[i - 1] else a.v end end
+        [(N(9000) + N(0)).v, N(7)[9000]]
+        "#,
+        );
+        let res = eval_both(&mut interpreter, &ast).unwrap();
+        assert_eq!(res.repr(), "[0, 7]");
+    }
 }
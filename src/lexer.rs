@@ -1,14 +1,58 @@
 use crate::atom::Atom;
-use std::mem::discriminant;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use unicode_xid::UnicodeXID;
 
-#[derive(Debug, PartialEq)]
-pub enum Token {
-    Symbol(String),
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    UnknownChar(char),
+    UnterminatedString,
+    UnterminatedComment,
+    InvalidInterpolation,
+    InvalidEscape(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownChar(c) => write!(f, "Unknown character {:?}", c),
+            Error::UnterminatedString => write!(f, "Unterminated string literal"),
+            Error::UnterminatedComment => write!(f, "Unterminated #= block comment"),
+            Error::InvalidInterpolation => {
+                write!(f, "Expected a name or bracket after $ in a string")
+            }
+            Error::InvalidEscape(s) => write!(f, "Invalid escape sequence in \"{}\"", s),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+//Symbols borrow from the source, strings only allocate when they contain escapes
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token<'a> {
+    Symbol(&'a str),
+    String(Cow<'a, str>),
+    //Interpolated strings, the parts between these are literal strings or bracketed
+    //expressions so "a $x" is lexed as StringStart String("a ") ( x ) StringEnd
+    StringStart,
+    StringEnd,
     //Numbers
     Float(f64),
     //Brackets
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    //Field access p.x
+    Dot,
+    //Type annotations x::Float
+    DoubleColon,
     //Mathematical Operators
     Plus,
     Minus,
@@ -29,22 +73,33 @@ pub enum Token {
     Else,
     ElseIf,
     End,
-    //WhiteSpace
+    Function,
+    While,
+    Try,
+    Catch,
+    Finally,
+    Return,
+    Struct,
+    Mutable,
+    //Statement terminators
     NewLine,
+    Semicolon,
 
     //Assignment
     Assign,
     //Unknown things
     EOF,
-    UnknownToken(String),
+    UnknownToken(&'a str),
 }
 
 //This block should be moved to the parser file?
-impl Token {
+impl<'a> Token<'a> {
     pub fn to_atom(&self) -> Atom {
         match self {
             Token::Float(value) => Atom::Float(*value),
+            Token::String(value) => Atom::String(value.to_string()),
             Token::Plus => Atom::Plus,
+            Token::Minus => Atom::Minus,
             Token::Power => Atom::Power,
             Token::Multiply => Atom::Multiply,
             Token::Divide => Atom::Divide,
@@ -62,121 +117,487 @@ impl Token {
 
     pub fn precedence(&self) -> isize {
         match self {
+            Self::Assign => 1,
             Self::Equal | Self::NotEqual => 5,
             Self::GreaterThan | Self::LessThan => 10,
             Self::Plus | Self::Minus => 20,
             Self::Multiply | Self::Divide => 30,
             Self::Power => 40,
-            Self::Assign => 50,
+            //Function calls f(x), indexing a[i] and field access p.x
+            Self::LParen | Self::LBracket | Self::Dot => 60,
             _ => 0,
         }
     }
 }
 
-pub struct Lexer {
-    tokens: Vec<String>,
+//Byte offsets into the source, end is exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+//A comment skipped over by the lexer, the text includes the # delimiters
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    pub text: String,
+    pub span: Span,
+}
+
+//Lexes the source in a single pass as tokens are asked for. Tokens are buffered so the
+//parser can look any number of tokens ahead.
+pub struct Lexer<'a> {
+    src: &'a str,
+    //Byte offset of the next character, always on a character boundary
     idx: usize,
+    buffer: VecDeque<(Token<'a>, Span)>,
+    //End of the last token handed out by advance
+    end: usize,
+    //Open brackets, newlines inside brackets don't end a statement
+    depth: usize,
+    //Set until a token is lexed on the current line so blank lines are collapsed
+    line_start: bool,
+    done: bool,
+    error: Option<Error>,
+    trivia: Vec<Trivia>,
 }
 
-impl Lexer {
-    pub fn new(tokens: Vec<String>) -> Self {
-        Lexer { tokens, idx: 0 }
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Lexer {
+            src,
+            idx: 0,
+            buffer: VecDeque::new(),
+            end: 0,
+            depth: 0,
+            line_start: true,
+            done: false,
+            error: None,
+            trivia: vec![],
+        }
     }
 
-    pub fn next(&mut self) -> Token {
-        let token = self.peek(0);
-        self.idx += 1;
-        token
+    //Returns the token n places ahead without consuming it, EOF past the end of the source
+    pub fn peek(&mut self, n: usize) -> Result<Token<'a>> {
+        self.fill(n)?;
+        Ok(self
+            .buffer
+            .get(n)
+            .map_or(Token::EOF, |(token, _)| token.clone()))
     }
 
-    pub fn peek(&mut self, n: usize) -> Token {
-        if self.idx + n < self.tokens.len() {
-            str_to_token(&self.tokens[self.idx + n])
+    //Where the token n places ahead is in the source, EOF is an empty span at the end
+    pub fn peek_span(&mut self, n: usize) -> Result<Span> {
+        self.fill(n)?;
+        let eof = Span {
+            start: self.src.len(),
+            end: self.src.len(),
+        };
+        Ok(self.buffer.get(n).map_or(eof, |(_, span)| *span))
+    }
+
+    pub fn advance(&mut self) -> Result<Token<'a>> {
+        self.fill(0)?;
+        match self.buffer.pop_front() {
+            Some((token, span)) => {
+                self.end = span.end;
+                Ok(token)
+            }
+            None => Ok(Token::EOF),
+        }
+    }
+
+    //The byte offset just past the last token handed out by advance
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    //The comments lexed so far, so tools such as a formatter can re-emit them
+    pub fn trivia(&self) -> &[Trivia] {
+        &self.trivia
+    }
+
+    //Lexes until the buffer holds n + 1 tokens or the source runs out. Tokens lexed before
+    //an error are still handed out, the error is returned once they're used up.
+    fn fill(&mut self, n: usize) -> Result<()> {
+        while self.buffer.len() <= n && !self.done {
+            match self.lex_token() {
+                Ok(more) => self.done = !more,
+                Err(error) => {
+                    self.done = true;
+                    self.error = Some(error);
+                }
+            }
+        }
+        match &self.error {
+            Some(error) if self.buffer.len() <= n => Err(error.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    //Buffers a token found between start and the current position
+    fn emit(&mut self, token: Token<'a>, start: usize) {
+        match token {
+            Token::LParen | Token::LBracket | Token::UnknownToken("{") => self.depth += 1,
+            Token::RParen | Token::RBracket | Token::UnknownToken("}") => {
+                self.depth = self.depth.saturating_sub(1)
+            }
+            _ => (),
+        }
+        self.line_start = token == Token::NewLine;
+        let span = Span {
+            start,
+            end: self.idx,
+        };
+        self.buffer.push_back((token, span));
+    }
+
+    //Lexes the next token into the buffer, or several for an interpolated string. Returns
+    //false at the end of the source.
+    fn lex_token(&mut self) -> Result<bool> {
+        let c = loop {
+            match self.peek_char(0) {
+                None => return Ok(false),
+                Some('\n') => {
+                    self.idx += 1;
+                    if self.depth == 0 && !self.line_start {
+                        self.emit(Token::NewLine, self.idx - 1);
+                        return Ok(true);
+                    }
+                }
+                Some('#') => self.skip_comment()?,
+                Some(c) if c.is_whitespace() => self.idx += c.len_utf8(),
+                Some(c) => break c,
+            }
+        };
+
+        let start = self.idx;
+        let token = match c {
+            '"' => {
+                self.lex_string()?;
+                return Ok(true);
+            }
+            c if c.is_ascii_digit() => {
+                self.skip_number();
+                let number = &self.src[start..self.idx];
+                Token::Float(number.parse().expect("Digits are a valid float"))
+            }
+            c if is_symbol_start(c) => {
+                self.skip_symbol();
+                keyword_or_symbol(&self.src[start..self.idx])
+            }
+            c => {
+                self.idx += c.len_utf8();
+                match (c, self.peek_char(0)) {
+                    ('!', Some('=')) => {
+                        self.idx += 1;
+                        Token::NotEqual
+                    }
+                    ('=', Some('=')) => {
+                        self.idx += 1;
+                        Token::Equal
+                    }
+                    (':', Some(':')) => {
+                        self.idx += 1;
+                        Token::DoubleColon
+                    }
+                    ('+', _) => Token::Plus,
+                    ('-', _) => Token::Minus,
+                    ('*', _) => Token::Multiply,
+                    ('/', _) => Token::Divide,
+                    ('^', _) => Token::Power,
+                    ('!', _) => Token::Not,
+                    ('<', _) => Token::LessThan,
+                    ('>', _) => Token::GreaterThan,
+                    ('=', _) => Token::Assign,
+                    ('(', _) => Token::LParen,
+                    (')', _) => Token::RParen,
+                    ('[', _) => Token::LBracket,
+                    (']', _) => Token::RBracket,
+                    (',', _) => Token::Comma,
+                    ('.', _) => Token::Dot,
+                    (';', _) => Token::Semicolon,
+                    ('{', _)
+                    | ('}', _)
+                    | (':', _)
+                    | ('&', _)
+                    | ('|', _)
+                    | ('%', _)
+                    | ('$', _)
+                    | ('@', _) => Token::UnknownToken(&self.src[start..self.idx]),
+                    (c, _) => return Err(Error::UnknownChar(c)),
+                }
+            }
+        };
+        self.emit(token, start);
+        Ok(true)
+    }
+
+    //Skips a # line comment or a #= =# block comment, block comments can be nested
+    fn skip_comment(&mut self) -> Result<()> {
+        let start = self.idx;
+
+        if self.peek_char(1) == Some('=') {
+            let mut depth = 0;
+            loop {
+                match (self.peek_char(0), self.peek_char(1)) {
+                    (Some('#'), Some('=')) => {
+                        depth += 1;
+                        self.idx += 2;
+                    }
+                    (Some('='), Some('#')) => {
+                        depth -= 1;
+                        self.idx += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    (Some(c), _) => self.idx += c.len_utf8(),
+                    (None, _) => return Err(Error::UnterminatedComment),
+                }
+            }
         } else {
-            Token::EOF
+            while let Some(c) = self.peek_char(0) {
+                if c == '\n' {
+                    break;
+                }
+                self.idx += c.len_utf8();
+            }
         }
+
+        let text = self.src[start..self.idx].to_string();
+        let span = Span {
+            start,
+            end: self.idx,
+        };
+        self.trivia.push(Trivia { text, span });
+        Ok(())
     }
 
-    pub fn expect(&mut self, token: &Token) -> Token {
-        let next_token = self.next();
-        if discriminant(token) != discriminant(&next_token) {
-            panic!(
-                "Unexpected token given {:?} expected {:?}",
-                token, next_token
-            )
+    //Emits a plain string as a single token, otherwise the parts of the interpolation
+    fn lex_string(&mut self) -> Result<()> {
+        let quote = self.idx;
+        self.idx += 1;
+        let mut start = self.idx;
+        let mut interpolated = false;
+
+        loop {
+            match self.peek_char(0) {
+                None => return Err(Error::UnterminatedString),
+                Some('"') => break,
+                //Skip over the escaped character so \" doesn't end the string
+                Some('\\') => {
+                    self.idx += 1;
+                    if let Some(c) = self.peek_char(0) {
+                        self.idx += c.len_utf8();
+                    }
+                }
+                Some('$') => {
+                    if !interpolated {
+                        self.emit(Token::StringStart, quote);
+                        interpolated = true;
+                    }
+                    if self.idx > start {
+                        let part = self.string(start, self.idx)?;
+                        self.emit(part, start);
+                    }
+
+                    let dollar = self.idx;
+                    self.idx += 1;
+                    match self.peek_char(0) {
+                        Some('(') => {
+                            self.idx += 1;
+                            let depth = self.depth;
+                            self.emit(Token::LParen, dollar);
+                            while self.depth > depth {
+                                if !self.lex_token()? {
+                                    return Err(Error::UnterminatedString);
+                                }
+                            }
+                        }
+                        Some(c) if is_symbol_start(c) => {
+                            let symbol = self.idx;
+                            self.skip_symbol();
+                            self.emit(Token::LParen, dollar);
+                            self.emit(Token::Symbol(&self.src[symbol..self.idx]), symbol);
+                            self.emit(Token::RParen, self.idx);
+                        }
+                        _ => return Err(Error::InvalidInterpolation),
+                    }
+                    start = self.idx;
+                }
+                Some(c) => self.idx += c.len_utf8(),
+            }
+        }
+
+        //The closing quote is part of the last token
+        let end = self.idx;
+        self.idx += 1;
+        if !interpolated {
+            let string = self.string(start, end)?;
+            self.emit(string, quote);
+        } else {
+            if end > start {
+                let part = self.string(start, end)?;
+                self.emit(part, start);
+            }
+            self.emit(Token::StringEnd, end);
+        }
+        Ok(())
+    }
+
+    //A string token for the source between start and end, resolving any escape sequences
+    fn string(&self, start: usize, end: usize) -> Result<Token<'a>> {
+        let s = &self.src[start..end];
+        if !s.contains('\\') {
+            return Ok(Token::String(Cow::Borrowed(s)));
+        }
+        match unescape(s) {
+            Some(value) => Ok(Token::String(Cow::Owned(value))),
+            None => Err(Error::InvalidEscape(s.to_string())),
+        }
+    }
+
+    fn peek_char(&self, n: usize) -> Option<char> {
+        self.src[self.idx..].chars().nth(n)
+    }
+
+    fn skip_digits(&mut self) {
+        while let Some(c) = self.peek_char(0) {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            self.idx += 1;
+        }
+    }
+
+    fn skip_number(&mut self) {
+        self.skip_digits();
+        //Only treat a dot as a decimal point when a digit follows it
+        if self.peek_char(0) == Some('.') && self.peek_char(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.idx += 1;
+            self.skip_digits();
+        }
+        if let Some('e') | Some('E') = self.peek_char(0) {
+            let sign = match self.peek_char(1) {
+                Some('+') | Some('-') => 1,
+                _ => 0,
+            };
+            if self.peek_char(1 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                self.idx += 1 + sign;
+                self.skip_digits();
+            }
         }
-        next_token
     }
 
-    pub fn all(&mut self) -> Vec<Token> {
-        let mut tokens = vec![];
-        while self.peek(0) != Token::EOF {
-            tokens.push(self.next())
+    //Skips the rest of a symbol, a ! is only part of the symbol when it isn't the start of !=
+    fn skip_symbol(&mut self) {
+        while let Some(c) = self.peek_char(0) {
+            if !is_symbol_char(c) || (c == '!' && self.peek_char(1) == Some('=')) {
+                break;
+            }
+            self.idx += c.len_utf8();
         }
-        self.idx = 0;
-        tokens
     }
 }
 
-fn str_to_token(token: &str) -> Token {
-    match token {
-        //Mathematical operators
-        "+" => Token::Plus,
-        "-" => Token::Minus,
-        "/" => Token::Divide,
-        "*" => Token::Multiply,
-        "^" => Token::Power,
-        //Logical Operators
-        "!" => Token::Not,
-        "<" => Token::LessThan,
-        ">" => Token::GreaterThan,
-        //Assignemnt
-        "=" => Token::Assign,
-        "!=" => Token::NotEqual,
-        "==" => Token::Equal,
-        //Brackets
-        "(" => Token::LParen,
-        ")" => Token::RParen,
-        //Keywords
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>>;
+
+    //Yields each token up to the end of the source, or up to and including the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(Token::EOF) => None,
+            Ok(token) => Some(Ok(token)),
+            Err(error) => {
+                self.error = None;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+fn keyword_or_symbol(s: &str) -> Token<'_> {
+    match s {
         "if" => Token::If,
         "then" => Token::Then,
         "else" => Token::Else,
         "elseif" => Token::ElseIf,
         "end" => Token::End,
-        "\n" => Token::NewLine,
+        "function" => Token::Function,
+        "while" => Token::While,
+        "try" => Token::Try,
+        "catch" => Token::Catch,
+        "finally" => Token::Finally,
+        "return" => Token::Return,
+        "struct" => Token::Struct,
+        "mutable" => Token::Mutable,
         "false" => Token::False,
         "true" => Token::True,
-
-        //Literals
-        token if is_f64(token) => Token::Float(token.parse::<f64>().unwrap()),
-        token if is_valid_symbol(token) => Token::Symbol(token.to_string()),
-        token => return Token::UnknownToken(token.to_string()),
+        s => Token::Symbol(s),
     }
 }
 
-fn is_f64(s: &str) -> bool {
-    s.parse::<f64>().is_ok()
+//Like Julia's identifiers, symbols start with a letter or underscore and continue with
+//letters, digits, underscores, exclamation marks and sub or superscript digits
+pub fn is_symbol_start(c: char) -> bool {
+    c == '_' || UnicodeXID::is_xid_start(c)
 }
 
-fn is_i64(s: &str) -> bool {
-    s.parse::<i64>().is_ok()
+pub fn is_symbol_char(c: char) -> bool {
+    c == '!'
+        || UnicodeXID::is_xid_continue(c)
+        || ('\u{2070}'..='\u{209f}').contains(&c)
+        || c == '\u{b2}'
+        || c == '\u{b3}'
+        || c == '\u{b9}'
 }
 
-fn is_valid_symbol(s: &str) -> bool {
-    s.chars().all(|c| c.is_alphabetic())
+//Resolves the escape sequences of a string literal, returning None for an invalid escape
+fn unescape(s: &str) -> Option<String> {
+    let mut value = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let escaped = match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '$' => '$',
+            '\\' => '\\',
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let hex: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+            }
+            _ => return None,
+        };
+        value.push(escaped);
+    }
+    Some(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tokenizer::tokenize;
     use indoc::indoc;
 
-    fn setup(s: &str) -> Vec<Token> {
-        let tokens = tokenize(s).expect("Error in tokenizing input");
-        let mut lexer = Lexer::new(tokens);
-        let tokens = lexer.all();
-        tokens
+    fn setup(s: &str) -> Vec<Token<'_>> {
+        Lexer::new(s)
+            .collect::<Result<_>>()
+            .expect("Error in lexing input")
+    }
+
+    fn string(s: &str) -> Token<'_> {
+        Token::String(s.into())
     }
 
     #[test]
@@ -184,9 +605,9 @@ mod tests {
         let tokens = setup("( hello ) mate");
         let ans = vec![
             Token::LParen,
-            Token::Symbol("hello".to_string()),
+            Token::Symbol("hello"),
             Token::RParen,
-            Token::Symbol("mate".to_string()),
+            Token::Symbol("mate"),
         ];
         assert_eq!(ans, tokens);
     }
@@ -194,24 +615,102 @@ mod tests {
     #[test]
     fn assigment() {
         let tokens = setup("x = 100");
+        let ans = vec![Token::Symbol("x"), Token::Assign, Token::Float(100.0)];
+        assert_eq!(ans, tokens);
+    }
+
+    #[test]
+    fn equals_and_doesnt_equal() {
+        let tokens = setup("x == y != z");
         let ans = vec![
-            Token::Symbol("x".to_string()),
-            Token::Assign,
-            Token::Float(100.0),
+            Token::Symbol("x"),
+            Token::Equal,
+            Token::Symbol("y"),
+            Token::NotEqual,
+            Token::Symbol("z"),
         ];
         assert_eq!(ans, tokens);
     }
 
-    // #[ignore]
     #[test]
-    fn doesnt_equal() {
-        let tokens = setup("x != y");
+    fn numbers() {
+        let tokens = setup("1.5 - 2e3 * 10");
         let ans = vec![
-            Token::Symbol("x".to_string()),
+            Token::Float(1.5),
+            Token::Minus,
+            Token::Float(2000.0),
+            Token::Multiply,
+            Token::Float(10.0),
+        ];
+        assert_eq!(ans, tokens);
+    }
+
+    #[test]
+    fn strings_and_arrays() {
+        let tokens = setup(r#"["a\tb", "\u{3b1}", "a \"b\" c", ""]"#);
+        let ans = vec![
+            Token::LBracket,
+            string("a\tb"),
+            Token::Comma,
+            string("α"),
+            Token::Comma,
+            string("a \"b\" c"),
+            Token::Comma,
+            string(""),
+            Token::RBracket,
+        ];
+        assert_eq!(ans, tokens);
+
+        //Strings without escapes are borrowed from the source
+        match Lexer::new(r#""plain""#).advance() {
+            Ok(Token::String(Cow::Borrowed("plain"))) => (),
+            res => panic!("Expected a borrowed string but got {:?}", res),
+        }
+        assert_eq!(
+            Lexer::new(r#""open"#).advance(),
+            Err(Error::UnterminatedString)
+        );
+        assert!(matches!(
+            Lexer::new(r#""\q""#).advance(),
+            Err(Error::InvalidEscape(_))
+        ));
+    }
+
+    #[test]
+    fn symbols_with_digits_and_unicode() {
+        let tokens = setup("x1 = my_var + push!(α₂, _tmp) != ünïcødé");
+        let ans = vec![
+            Token::Symbol("x1"),
+            Token::Assign,
+            Token::Symbol("my_var"),
+            Token::Plus,
+            Token::Symbol("push!"),
+            Token::LParen,
+            Token::Symbol("α₂"),
+            Token::Comma,
+            Token::Symbol("_tmp"),
+            Token::RParen,
             Token::NotEqual,
-            Token::Symbol("y".to_string()),
+            Token::Symbol("ünïcødé"),
         ];
         assert_eq!(ans, tokens);
+
+        let tokens = setup(r#""é$ü!""#);
+        let ans = vec![
+            Token::StringStart,
+            string("é"),
+            Token::LParen,
+            Token::Symbol("ü!"),
+            Token::RParen,
+            Token::StringEnd,
+        ];
+        assert_eq!(ans, tokens);
+
+        //Tokens before an error are still returned
+        let mut lexer = Lexer::new("a → b");
+        assert_eq!(lexer.next(), Some(Ok(Token::Symbol("a"))));
+        assert_eq!(lexer.next(), Some(Err(Error::UnknownChar('→'))));
+        assert_eq!(lexer.next(), None);
     }
 
     #[test]
@@ -228,14 +727,19 @@ mod tests {
         let tokens = setup(s);
         let ans = vec![
             Token::If,
-            Token::Symbol("x".to_string()),
+            Token::Symbol("x"),
             Token::GreaterThan,
-            Token::Symbol("y".to_string()),
+            Token::Symbol("y"),
             Token::Then,
-            Token::Symbol("x".to_string()),
+            Token::NewLine,
+            Token::Symbol("x"),
+            Token::NewLine,
             Token::Else,
-            Token::Symbol("y".to_string()),
+            Token::NewLine,
+            Token::Symbol("y"),
+            Token::NewLine,
             Token::End,
+            Token::NewLine,
         ];
         assert_eq!(ans, tokens);
     }
@@ -254,19 +758,138 @@ mod tests {
         let tokens = setup(s);
         let ans = vec![
             Token::If,
-            Token::Symbol("x".to_string()),
+            Token::Symbol("x"),
             Token::GreaterThan,
-            Token::Symbol("y".to_string()),
+            Token::Symbol("y"),
             Token::Then,
-            Token::Symbol("x".to_string()),
+            Token::NewLine,
+            Token::Symbol("x"),
+            Token::NewLine,
             Token::ElseIf,
-            Token::Symbol("y".to_string()),
+            Token::Symbol("y"),
             Token::GreaterThan,
-            Token::Symbol("x".to_string()),
+            Token::Symbol("x"),
             Token::Then,
-            Token::Symbol("y".to_string()),
+            Token::NewLine,
+            Token::Symbol("y"),
+            Token::NewLine,
             Token::End,
+            Token::NewLine,
         ];
         assert_eq!(ans, tokens);
     }
+
+    #[test]
+    fn newlines() {
+        let s = indoc!(
+            "
+
+            x = f(1,
+                  2)
+
+            y = [3,
+                 4]; z
+        "
+        );
+        let tokens = setup(s);
+        let ans = vec![
+            Token::Symbol("x"),
+            Token::Assign,
+            Token::Symbol("f"),
+            Token::LParen,
+            Token::Float(1.0),
+            Token::Comma,
+            Token::Float(2.0),
+            Token::RParen,
+            Token::NewLine,
+            Token::Symbol("y"),
+            Token::Assign,
+            Token::LBracket,
+            Token::Float(3.0),
+            Token::Comma,
+            Token::Float(4.0),
+            Token::RBracket,
+            Token::Semicolon,
+            Token::Symbol("z"),
+            Token::NewLine,
+        ];
+        assert_eq!(ans, tokens);
+    }
+
+    #[test]
+    fn comments() {
+        let s = indoc!(
+            "
+            x = 1 # the first value
+            #= a block #= with a nested =# comment
+               over two lines =# y
+            # x = 2
+        "
+        );
+        let mut lexer = Lexer::new(s);
+        let tokens: Vec<Token> = lexer.by_ref().map(|t| t.unwrap()).collect();
+        let ans = vec![
+            Token::Symbol("x"),
+            Token::Assign,
+            Token::Float(1.0),
+            Token::NewLine,
+            Token::Symbol("y"),
+            Token::NewLine,
+        ];
+        assert_eq!(ans, tokens);
+
+        let comments: Vec<&str> = lexer.trivia().iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(
+            comments,
+            [
+                "# the first value",
+                "#= a block #= with a nested =# comment\n   over two lines =#",
+                "# x = 2"
+            ]
+        );
+        for t in lexer.trivia().iter() {
+            assert_eq!(&s[t.span.start..t.span.end], t.text);
+        }
+
+        let res: Result<Vec<Token>> = Lexer::new("#= #= =# x").collect();
+        assert_eq!(res, Err(Error::UnterminatedComment));
+    }
+
+    #[test]
+    fn interpolation() {
+        let tokens = setup(r#""a $x b $(f("(", y)) \$c""#);
+        let ans = vec![
+            Token::StringStart,
+            string("a "),
+            Token::LParen,
+            Token::Symbol("x"),
+            Token::RParen,
+            string(" b "),
+            Token::LParen,
+            Token::Symbol("f"),
+            Token::LParen,
+            string("("),
+            Token::Comma,
+            Token::Symbol("y"),
+            Token::RParen,
+            Token::RParen,
+            string(" $c"),
+            Token::StringEnd,
+        ];
+        assert_eq!(ans, tokens);
+
+        let res: Result<Vec<Token>> = Lexer::new(r#""$(x""#).collect();
+        assert!(res.is_err());
+        let res: Result<Vec<Token>> = Lexer::new(r#""cost $5""#).collect();
+        assert_eq!(res, Err(Error::InvalidInterpolation));
+    }
+
+    #[test]
+    fn peeking_ahead() {
+        let mut lexer = Lexer::new("a + b");
+        assert_eq!(lexer.peek(2), Ok(Token::Symbol("b")));
+        assert_eq!(lexer.peek(5), Ok(Token::EOF));
+        assert_eq!(lexer.advance(), Ok(Token::Symbol("a")));
+        assert_eq!(lexer.peek(0), Ok(Token::Plus));
+    }
 }
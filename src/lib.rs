@@ -1,7 +1,15 @@
 pub mod atom;
+pub mod capabilities;
+pub mod checker;
+pub mod compiler;
 pub mod enviroment;
 pub mod interpreter;
 pub mod lexer;
 pub mod object;
+pub mod optimiser;
 pub mod parser;
-pub mod tokenizer;
+pub mod resolver;
+pub mod stdlib;
+pub mod symbol;
+pub mod types;
+pub mod vm;
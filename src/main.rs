@@ -1,3 +1,46 @@
+use lemurlib::checker;
+use std::env;
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage: lemur check <file>";
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = match args.as_slice() {
+        [command, path] if command == "check" => check(path),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    };
+    process::exit(code);
+}
+
+//Prints each error the checker finds as path:line:column: error, without running the script
+fn check(path: &str) -> i32 {
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return 2;
+        }
+    };
+    match checker::check(&src) {
+        Ok(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                let (line, column) = checker::position(&src, diagnostic.span.start);
+                println!("{}:{}:{}: {}", path, line, column, diagnostic.error);
+            }
+            if diagnostics.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(error) => {
+            println!("{}: SyntaxError: {}", path, error);
+            1
+        }
+    }
 }
@@ -1,10 +1,380 @@
+use crate::atom::{self, Function, Struct};
+use crate::enviroment::Frame;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::symbol::Symbol;
+use crate::types::Type;
+use std::cell::RefCell;
 use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::ptr;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq, Clone)]
+pub type NativeFn = fn(&mut Interpreter, &[Object]) -> Result<Object>;
+
+#[derive(Debug, Clone)]
 pub enum Object {
     String(String),
-    Symbol(String),
+    Symbol(Symbol),
     Float(f64),
     Bool(bool),
+    Array(Rc<RefCell<Vec<Object>>>),
+    Builtin(Builtin),
+    Function(Closure),
+    File(Rc<FileHandle>),
+    Error(Rc<ErrorValue>),
+    //A function with several methods, the one called is picked by the argument types
+    Generic(Rc<Generic>),
+    Struct(Rc<Instance>),
+    Type(Type),
+    //The process environment, indexed by variable name
+    Env,
     Nothing,
 }
+
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: NativeFn,
+}
+
+//Builtins are registered once under a unique name so comparing names is enough
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+//A function defined in a script along with the frame it was defined in
+#[derive(Clone)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub frame: Option<Rc<Frame>>,
+}
+
+//Closures are equal when they come from the same definition evaluated in the same frame
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        let same_frame = match (&self.frame, &other.frame) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        Rc::ptr_eq(&self.function, &other.function) && same_frame
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Function({})", self.function.name)
+    }
+}
+
+//The methods of a function defined more than once with different signatures, in the
+//order they were defined
+pub struct Generic {
+    pub name: Symbol,
+    pub methods: RefCell<Vec<Closure>>,
+}
+
+//Defining a method adds it to the existing generic function, which is only equal to itself
+impl PartialEq for Generic {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Generic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generic({}, {:?})", self.name, self.methods.borrow())
+    }
+}
+
+//A caught error along with the scripted functions being called when it was raised,
+//outermost first
+#[derive(Debug, PartialEq)]
+pub struct ErrorValue {
+    pub error: Error,
+    pub trace: Vec<Symbol>,
+}
+
+//A file opened by a script, closing it drops the file so any later use is an error
+pub struct FileHandle {
+    pub path: PathBuf,
+    pub file: RefCell<Option<BufReader<File>>>,
+}
+
+//Each call to open gives a new handle, which is only equal to itself
+impl PartialEq for FileHandle {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "File({})", self.path.display())
+    }
+}
+
+//The field values of a struct instance in the order they were declared
+pub struct Instance {
+    pub ty: Rc<Struct>,
+    pub fields: RefCell<Vec<Object>>,
+}
+
+//Instances are equal when they're of the same struct and their fields are equal
+impl PartialEq for Instance {
+    fn eq(&self, other: &Self) -> bool {
+        Equality::default().instances(self, other)
+    }
+}
+
+//Arrays and instances are compared by their contents, see Equality
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        Equality::default().eq(self, other)
+    }
+}
+
+//Compares values that can contain themselves. A pair of arrays or instances that is
+//already being compared further up is taken to be equal, so two values that only
+//differ in where their cycles point are equal rather than recursing forever.
+#[derive(Default)]
+struct Equality {
+    comparing: Vec<(*const Items, *const Items)>,
+}
+
+type Items = RefCell<Vec<Object>>;
+
+impl Equality {
+    fn eq(&mut self, a: &Object, b: &Object) -> bool {
+        match (a, b) {
+            (Object::String(a), Object::String(b)) => a == b,
+            (Object::Symbol(a), Object::Symbol(b)) => a == b,
+            (Object::Float(a), Object::Float(b)) => a == b,
+            (Object::Bool(a), Object::Bool(b)) => a == b,
+            (Object::Array(a), Object::Array(b)) => self.items(a, b),
+            (Object::Builtin(a), Object::Builtin(b)) => a == b,
+            (Object::Function(a), Object::Function(b)) => a == b,
+            (Object::File(a), Object::File(b)) => a == b,
+            (Object::Error(a), Object::Error(b)) => a == b,
+            (Object::Generic(a), Object::Generic(b)) => a == b,
+            (Object::Struct(a), Object::Struct(b)) => self.instances(a, b),
+            (Object::Type(a), Object::Type(b)) => a == b,
+            (Object::Env, Object::Env) | (Object::Nothing, Object::Nothing) => true,
+            _ => false,
+        }
+    }
+
+    fn instances(&mut self, a: &Instance, b: &Instance) -> bool {
+        Rc::ptr_eq(&a.ty, &b.ty) && self.items(&a.fields, &b.fields)
+    }
+
+    fn items(&mut self, a: &Items, b: &Items) -> bool {
+        let pair = (a as *const _, b as *const _);
+        if self.comparing.contains(&pair) {
+            return true;
+        }
+        self.comparing.push(pair);
+        let (a, b) = (a.borrow(), b.borrow());
+        let equal = a.len() == b.len()
+            && atom::grow_stack(|| a.iter().zip(b.iter()).all(|(a, b)| self.eq(a, b)));
+        self.comparing.pop();
+        equal
+    }
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{:?}", self.ty.name, self.fields.borrow())
+    }
+}
+
+impl Object {
+    pub fn array(items: Vec<Object>) -> Self {
+        Object::Array(Rc::new(RefCell::new(items)))
+    }
+
+    /// The representation used by `show` and `repr`, which can be read back by the parser.
+    pub fn repr(&self) -> String {
+        let mut s = String::new();
+        Printer::new(&mut s, true)
+            .write(self)
+            .expect("Writing to a String cannot fail");
+        s
+    }
+}
+
+impl Object {
+    /// Writes the printed form, or the parseable one if `quote` is set, to any writer.
+    pub fn write_to<W: fmt::Write>(&self, out: &mut W, quote: bool) -> fmt::Result {
+        Printer::new(out, quote).write(self)
+    }
+}
+
+/// The representation used by `print` and `string`, strings are written without quotes.
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, false).write(self)
+    }
+}
+
+struct Printer<'a, W: fmt::Write> {
+    out: &'a mut W,
+    quote: bool,
+    //The arrays and instances currently being written, used to detect cycles
+    seen: Vec<*const RefCell<Vec<Object>>>,
+}
+
+impl<'a, W: fmt::Write> Printer<'a, W> {
+    fn new(out: &'a mut W, quote: bool) -> Self {
+        Printer {
+            out,
+            quote,
+            seen: vec![],
+        }
+    }
+
+    fn write(&mut self, object: &Object) -> fmt::Result {
+        match object {
+            Object::String(s) if self.quote => write_quoted(self.out, s),
+            Object::String(s) => self.out.write_str(s),
+            Object::Symbol(s) => write!(self.out, ":{}", s),
+            Object::Float(x) => write_float(self.out, *x),
+            Object::Bool(b) => write!(self.out, "{}", b),
+            Object::Array(items) => self.write_items("[", items, "]"),
+            Object::Struct(instance) => {
+                let open = format!("{}(", instance.ty.name);
+                self.write_items(&open, &instance.fields, ")")
+            }
+            Object::Type(ty) => self.out.write_str(&ty.name()),
+            Object::Builtin(builtin) => self.out.write_str(builtin.name),
+            Object::Function(closure) => write!(self.out, "{}", closure.function.name),
+            Object::Generic(generic) => write!(self.out, "{}", generic.name),
+            Object::File(handle) => write!(self.out, "<file {}>", handle.path.display()),
+            Object::Error(value) if self.quote => {
+                write!(self.out, "{}(", value.error.kind())?;
+                write_quoted(self.out, &value.error.message())?;
+                self.out.write_char(')')
+            }
+            Object::Error(value) => write!(self.out, "{}", value.error),
+            Object::Env => self.out.write_str("ENV"),
+            Object::Nothing => self.out.write_str("nothing"),
+        }
+    }
+
+    fn write_items(
+        &mut self,
+        open: &str,
+        items: &RefCell<Vec<Object>>,
+        close: &str,
+    ) -> fmt::Result {
+        let ptr = items as *const RefCell<Vec<Object>>;
+        if self.seen.contains(&ptr) {
+            return write!(self.out, "{}...{}", open, close);
+        }
+        self.seen.push(ptr);
+
+        //Elements are always quoted so ["a"] and [a] can be told apart
+        let quote = self.quote;
+        self.quote = true;
+        self.out.write_str(open)?;
+        for (i, item) in items.borrow().iter().enumerate() {
+            if i > 0 {
+                self.out.write_str(", ")?;
+            }
+            self.write(item)?;
+        }
+        self.out.write_str(close)?;
+        self.quote = quote;
+
+        self.seen.pop();
+        Ok(())
+    }
+}
+
+fn write_float<W: fmt::Write>(out: &mut W, x: f64) -> fmt::Result {
+    if x.is_nan() {
+        out.write_str("NaN")
+    } else if x.is_infinite() {
+        out.write_str(if x > 0.0 { "Inf" } else { "-Inf" })
+    } else {
+        //Rust already prints the shortest form that round trips, without a trailing .0
+        write!(out, "{}", x)
+    }
+}
+
+fn write_quoted<W: fmt::Write>(out: &mut W, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '$' => out.write_str("\\$")?,
+            '\n' => out.write_str("\\n")?,
+            '\t' => out.write_str("\\t")?,
+            '\r' => out.write_str("\\r")?,
+            '\0' => out.write_str("\\0")?,
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats() {
+        assert_eq!(Object::Float(90.0).to_string(), "90");
+        assert_eq!(Object::Float(2.5).to_string(), "2.5");
+        assert_eq!(Object::Float(-0.125).to_string(), "-0.125");
+        assert_eq!(Object::Float(f64::INFINITY).to_string(), "Inf");
+        assert_eq!(Object::Float(f64::NAN).repr(), "NaN");
+    }
+
+    #[test]
+    fn strings() {
+        let s = Object::String("say \"hi\"\n".to_string());
+        assert_eq!(s.to_string(), "say \"hi\"\n");
+        assert_eq!(s.repr(), "\"say \\\"hi\\\"\\n\"");
+    }
+
+    #[test]
+    fn nested_arrays() {
+        let inner = Object::array(vec![Object::String("a".to_string()), Object::Nothing]);
+        let outer = Object::array(vec![Object::Float(1.0), inner, Object::Bool(true)]);
+        assert_eq!(outer.to_string(), "[1, [\"a\", nothing], true]");
+        assert_eq!(outer.repr(), outer.to_string());
+    }
+
+    #[test]
+    fn cyclic_array() {
+        let items = Rc::new(RefCell::new(vec![Object::Float(1.0)]));
+        let array = Object::Array(items.clone());
+        items.borrow_mut().push(array.clone());
+        assert_eq!(array.repr(), "[1, [...]]");
+
+        //The same array appearing twice without a cycle is printed in full
+        let shared = Object::array(vec![Object::Float(2.0)]);
+        let pair = Object::array(vec![shared.clone(), shared]);
+        assert_eq!(pair.repr(), "[[2], [2]]");
+        items.borrow_mut().clear();
+    }
+
+    #[test]
+    fn symbols_and_nothing() {
+        assert_eq!(Object::Symbol(Symbol::intern("x")).repr(), ":x");
+        assert_eq!(Object::Nothing.to_string(), "nothing");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::atom::{self, Atom, Node, AST};
+use crate::interpreter::{eval_gt, eval_math};
+use crate::object::Object;
+use std::mem;
+use std::rc::Rc;
+
+//Rewrites the tree bottom up without changing what it evaluates to, including any error
+//it raises. Constant arithmetic and comparisons are folded, branches on a constant
+//condition are replaced by the branch taken, nested blocks are flattened and statements
+//whose value is unused and that can't have an effect are removed.
+pub fn optimise(ast: &mut AST) {
+    atom::grow_stack(|| optimise_node(ast))
+}
+
+fn optimise_node(ast: &mut AST) {
+    match ast {
+        AST::Atom(Atom::Function(function)) => {
+            if let Some(function) = Rc::get_mut(function) {
+                optimise(&mut function.body);
+            }
+        }
+        AST::Atom(_) => (),
+        AST::Node(node) => {
+            for ast in node.tail.iter_mut() {
+                optimise(ast);
+            }
+            if let Some(folded) = fold(node) {
+                *ast = folded;
+            }
+        }
+    }
+}
+
+fn fold(node: &mut Node) -> Option<AST> {
+    let tail = &mut node.tail;
+    match &node.head {
+        Atom::Minus if tail.len() == 1 => match constant(&tail[0])? {
+            Object::Float(x) => Some(AST::Atom(Atom::Float(-x))),
+            _ => None,
+        },
+        Atom::Plus | Atom::Minus | Atom::Multiply | Atom::Divide | Atom::Power => {
+            match (constant(&tail[0])?, constant(&tail[1])?) {
+                (x @ Object::Float(_), y @ Object::Float(_)) => {
+                    literal(eval_math(&node.head, &x, &y).ok()?)
+                }
+                _ => None,
+            }
+        }
+        Atom::GreaterThan | Atom::LessThan => {
+            let (a, b) = (constant(&tail[0])?, constant(&tail[1])?);
+            let result = match node.head {
+                Atom::GreaterThan => eval_gt(&a, &b),
+                _ => eval_gt(&b, &a),
+            };
+            literal(result.ok()?)
+        }
+        Atom::Equal | Atom::NotEqual => {
+            let equal = constant(&tail[0])? == constant(&tail[1])?;
+            literal(Object::Bool(equal == (node.head == Atom::Equal)))
+        }
+        Atom::If | Atom::ElseIf => match &tail[0] {
+            AST::Atom(Atom::True) => Some(tail.swap_remove(1)),
+            AST::Atom(Atom::False) => Some(tail.swap_remove(2)),
+            _ => None,
+        },
+        Atom::While => match &tail[0] {
+            AST::Atom(Atom::False) => Some(AST::Atom(Atom::Nothing)),
+            _ => None,
+        },
+        Atom::Block => {
+            let statements = mem::take(tail);
+            let last = statements.len().saturating_sub(1);
+            for (i, statement) in statements.into_iter().enumerate() {
+                match statement {
+                    AST::Node(Node {
+                        head: Atom::Block,
+                        tail: inner,
+                        ..
+                    }) if !inner.is_empty() => tail.extend(inner),
+                    //An empty block is nothing, which only matters as the last statement
+                    AST::Node(Node {
+                        head: Atom::Block, ..
+                    }) if i == last => tail.push(AST::Atom(Atom::Nothing)),
+                    statement => tail.push(statement),
+                }
+            }
+            //Only the value of the last statement is used
+            let last = tail.pop();
+            tail.retain(|statement| !pure(statement));
+            tail.extend(last);
+            None
+        }
+        _ => None,
+    }
+}
+
+//The value of a literal, None for anything that has to be evaluated
+fn constant(ast: &AST) -> Option<Object> {
+    match ast {
+        AST::Atom(Atom::Float(x)) => Some(Object::Float(*x)),
+        AST::Atom(Atom::String(s)) => Some(Object::String(s.clone())),
+        AST::Atom(Atom::True) => Some(Object::Bool(true)),
+        AST::Atom(Atom::False) => Some(Object::Bool(false)),
+        _ => None,
+    }
+}
+
+fn literal(object: Object) -> Option<AST> {
+    match object {
+        Object::Float(x) => Some(AST::Atom(Atom::Float(x))),
+        Object::Bool(true) => Some(AST::Atom(Atom::True)),
+        Object::Bool(false) => Some(AST::Atom(Atom::False)),
+        _ => None,
+    }
+}
+
+//Whether evaluating the expression can neither have an effect nor raise an error.
+//Variables aren't pure as reading one that isn't defined is an error.
+fn pure(ast: &AST) -> bool {
+    match ast {
+        AST::Atom(atom) => matches!(
+            atom,
+            Atom::Float(_)
+                | Atom::String(_)
+                | Atom::True
+                | Atom::False
+                | Atom::Nothing
+                | Atom::Function(_)
+                | Atom::Struct(_)
+        ),
+        AST::Node(node) => {
+            matches!(node.head, Atom::Array | Atom::Interpolate | Atom::Block)
+                && node.tail.iter().all(pure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enviroment::Enviroment;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::symbol::Symbol;
+    use crate::vm::eval_both;
+    use indoc::indoc;
+
+    fn parse(s: &str, optimise: bool) -> AST {
+        let mut parser = Parser::new(Lexer::new(s));
+        parser.set_optimise(optimise);
+        parser.parse_program().unwrap()
+    }
+
+    fn block(tail: Vec<AST>) -> AST {
+        AST::Node(Node::new(Atom::Block, tail))
+    }
+
+    #[test]
+    fn constant_folding() {
+        let ast = parse(r#"[3 ^ 2 * 10, -(1 + 1) < 0, "a" == "a", 1 != true]"#, true);
+        let ans = block(vec![AST::Node(Node::new(
+            Atom::Array,
+            vec![
+                AST::Atom(Atom::Float(90.0)),
+                AST::Atom(Atom::True),
+                AST::Atom(Atom::True),
+                AST::Atom(Atom::True),
+            ],
+        ))]);
+        assert_eq!(ast, ans);
+
+        //Folding an error would hide it so it's left for the interpreter to raise
+        let ast = parse(r#"x = 1 + "a""#, true);
+        assert_eq!(ast, parse(r#"x = 1 + "a""#, false));
+    }
+
+    #[test]
+    fn dead_branches_and_blocks() {
+        let s = indoc!(
+            "
+            if 1 > 2 then
+                a
+            elseif true then
+                b
+                c
+            else
+                d
+            end
+            while false
+                e
+            end
+            [1, 2]
+            x
+        "
+        );
+        let symbol = |s| AST::Atom(Atom::Symbol(Symbol::intern(s)));
+        let ans = block(vec![symbol("b"), symbol("c"), symbol("x")]);
+        assert_eq!(parse(s, true), ans);
+    }
+
+    #[test]
+    fn function_bodies() {
+        let ast = parse("function f() 2 * 3 end", true);
+        let body = match &ast {
+            AST::Node(node) => match &node.tail[0] {
+                AST::Node(assign) => match &assign.tail[1] {
+                    AST::Atom(Atom::Function(function)) => &function.body,
+                    ast => panic!("Expected a function but got {:?}", ast),
+                },
+                ast => panic!("Expected an assignment but got {:?}", ast),
+            },
+            ast => panic!("Expected a block but got {:?}", ast),
+        };
+        assert_eq!(body, &block(vec![AST::Atom(Atom::Float(6.0))]));
+    }
+
+    #[test]
+    fn semantics_are_preserved() {
+        let scripts = [
+            "3 ^ 2 * 10 - -4 / 2",
+            "x = 2; if x > 1 then 1; 2 else 3 end",
+            "if true then end",
+            "if false then 1 end",
+            "if 2 > 1 then 1 elseif y then 2 end",
+            "a = if false then 1 elseif 1 < 2 then [3, 4] end; a",
+            r#"1; "unused"; [1, "a"]; "$(1 + 2) $("x")""#,
+            "y = 1; while false y = 2 end; y",
+            "undefined_name; 1",
+            r#"1 + "a"; 2"#,
+            "if 1 then 2 end",
+            "function f(n) if n < 1 then 0 else 1 + 1 + f(n - 1) end end; f(5)",
+            "nan = 0 / 0; nan == nan",
+        ];
+        for s in scripts.iter() {
+            let expected = eval_both(&mut Interpreter::new(Enviroment::new()), &parse(s, false));
+            let result = eval_both(&mut Interpreter::new(Enviroment::new()), &parse(s, true));
+            assert_eq!(
+                expected.map(|o| o.repr()),
+                result.map(|o| o.repr()),
+                "Optimising changed the result of {}",
+                s
+            );
+        }
+    }
+}
@@ -1,287 +1,847 @@
-use crate::atom::{Atom, Node, AST};
-use crate::lexer::{Lexer, Token};
+use crate::atom::{self, Atom, Function, Node, Struct, AST};
+use crate::lexer::{self, Lexer, Span, Token};
+use crate::optimiser;
+use crate::resolver;
+use crate::symbol::Symbol;
+use std::error;
+use std::fmt;
+use std::rc::Rc;
 
-pub struct Parser {
-    lexer: Lexer,
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    Lex(lexer::Error),
+    UnexpectedToken(String),
+    InvalidSyntax(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Lex(error) => write!(f, "{}", error),
+            Error::UnexpectedToken(msg) => write!(f, "{}", msg),
+            Error::InvalidSyntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<lexer::Error> for Error {
+    fn from(error: lexer::Error) -> Self {
+        Error::Lex(error)
+    }
+}
+
+//How deeply expressions can nest, each bracket, operator and block counts as a level.
+//Every pass over the tree recurses through it, so this keeps them all well inside the
+//stack rather than letting a script crash the process.
+pub const MAX_NESTING: usize = 1000;
+
+//Names from the source go through the thread's bounded table, see symbol::MAX_NAME_BYTES
+fn intern(name: &str) -> Result<Symbol> {
+    Symbol::try_intern(name).ok_or_else(|| {
+        Error::InvalidSyntax(format!(
+            "Too many distinct names have been parsed on this thread to add {}",
+            name
+        ))
+    })
+}
+
+fn unexpected<T>(msg: String) -> Result<T> {
+    Err(Error::UnexpectedToken(msg))
+}
+
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    //How many function definitions are being parsed, each outermost one is resolved
+    //once it's complete so nested functions can see the locals around them
+    functions: usize,
+    //How many levels the expression being parsed is nested, see MAX_NESTING
+    depth: usize,
+    optimise: bool,
+    top_level_return: bool,
 }
 
-impl Parser {
-    pub fn new(lexer: Lexer) -> Self {
-        Parser { lexer }
+impl<'a> Parser<'a> {
+    pub fn new(lexer: Lexer<'a>) -> Self {
+        Parser {
+            lexer,
+            functions: 0,
+            depth: 0,
+            optimise: true,
+            top_level_return: true,
+        }
     }
 
-    fn nud(&mut self, token: Token) -> AST {
-        match token {
+    //Whether parse_program runs the optimiser over the tree, on by default
+    pub fn set_optimise(&mut self, optimise: bool) {
+        self.optimise = optimise;
+    }
+
+    //Whether return can be used outside of a function to end the script early, when it
+    //can't it's a syntax error. Allowed by default.
+    pub fn set_top_level_return(&mut self, allowed: bool) {
+        self.top_level_return = allowed;
+    }
+
+    //Parses a whole script, which unlike a block has to run to the end of the source
+    pub fn parse_program(&mut self) -> Result<AST> {
+        let mut ast = self.parse_block()?;
+        match self.lexer.peek(0)? {
+            Token::EOF => (),
+            token => return unexpected(format!("Unexpected token {:?} in script", token)),
+        }
+        if self.optimise {
+            optimiser::optimise(&mut ast);
+        }
+        Ok(ast)
+    }
+
+    fn nud(&mut self, token: Token<'a>) -> Result<AST> {
+        let ast = match token {
             Token::False => AST::Atom(Atom::False),
             Token::True => AST::Atom(Atom::True),
             Token::Float(value) => AST::Atom(Atom::Float(value)),
+            Token::String(value) => AST::Atom(Atom::String(value.into_owned())),
             Token::LParen => {
-                let inner = self._parse(token.precedence());
-                self.lexer.expect(&Token::RParen);
+                let inner = self.parse()?;
+                self.expect(&Token::RParen)?;
                 inner
             }
-            Token::Plus => self._parse(token.precedence()),
+            //Each part is either a literal string or a bracketed expression
+            Token::StringStart => {
+                let mut tail = vec![];
+                loop {
+                    match self.lexer.advance()? {
+                        Token::String(value) => {
+                            tail.push(AST::Atom(Atom::String(value.into_owned())))
+                        }
+                        Token::LParen => {
+                            tail.push(self.parse()?);
+                            self.expect(&Token::RParen)?;
+                        }
+                        Token::StringEnd => break,
+                        token => {
+                            return unexpected(format!(
+                                "Unexpected token in string interpolation {:?}",
+                                token
+                            ))
+                        }
+                    }
+                }
+                AST::Node(Node::new(Atom::Interpolate, tail))
+            }
+            Token::LBracket => {
+                let tail = self.parse_list(&Token::RBracket)?;
+                AST::Node(Node::new(Atom::Array, tail))
+            }
+            Token::Plus => self._parse(token.precedence())?,
             Token::Minus => {
-                let tail = vec![self._parse(token.precedence())];
-                let head = Atom::Symbol("-".to_string());
-                let node = Node { head, tail };
+                let tail = vec![self._parse(token.precedence())?];
+                let head = Atom::Minus;
+                let node = Node::new(head, tail);
                 AST::Node(node)
             }
 
             Token::If => {
-                let condition = self.parse();
-                self.lexer.expect(&Token::Then);
-                let block = self.parse_block();
-                let next_token = self.lexer.peek(0);
+                let condition = self.parse()?;
+                self.expect(&Token::Then)?;
+                let block = self.parse_block()?;
+                let next_token = self.lexer.peek(0)?;
 
                 match next_token {
                     Token::End => {
-                        self.lexer.next();
-                        AST::Node(Node {
-                            head: Atom::If,
-                            tail: vec![condition, block, AST::Atom(Atom::Nothing)],
-                        })
+                        self.lexer.advance()?;
+                        AST::Node(Node::new(
+                            Atom::If,
+                            vec![condition, block, AST::Atom(Atom::Nothing)],
+                        ))
+                    }
+                    Token::Else | Token::ElseIf => {
+                        AST::Node(Node::new(Atom::If, vec![condition, block, self.parse()?]))
+                    }
+                    _ => {
+                        return unexpected(format!(
+                            "Unexpected token after if block {:?}",
+                            next_token
+                        ))
                     }
-                    Token::Else | Token::ElseIf => AST::Node(Node {
-                        head: Atom::If,
-                        tail: vec![condition, block, self.parse()],
-                    }),
-                    _ => panic!("Unexpected token after if block {:?}", next_token),
                 }
             }
 
             Token::ElseIf => {
-                let elseif_condition = self.parse();
-                self.lexer.expect(&Token::Then);
-                let elseif_block = self.parse_block();
+                let elseif_condition = self.parse()?;
+                self.expect(&Token::Then)?;
+                let elseif_block = self.parse_block()?;
 
-                let other_block = match self.lexer.peek(0) {
+                let other_block = match self.lexer.peek(0)? {
                     Token::End => {
-                        self.lexer.next();
+                        self.lexer.advance()?;
                         AST::Atom(Atom::Nothing)
                     }
-                    Token::ElseIf | Token::Else => self.parse(),
-                    token => panic!("Unexpected token after elseif {:?}", token),
+                    Token::ElseIf | Token::Else => self.parse()?,
+                    token => {
+                        return unexpected(format!("Unexpected token after elseif {:?}", token))
+                    }
                 };
 
-                AST::Node(Node {
-                    head: Atom::ElseIf,
-                    tail: vec![elseif_condition, elseif_block, other_block],
-                })
+                AST::Node(Node::new(
+                    Atom::ElseIf,
+                    vec![elseif_condition, elseif_block, other_block],
+                ))
             }
 
             Token::Else => {
-                let ast = self.parse_block();
-                self.lexer.expect(&Token::End);
+                let ast = self.parse_block()?;
+                self.expect(&Token::End)?;
                 ast
             }
-            Token::Symbol(value) => AST::Atom(Atom::Symbol(value)),
-            _ => panic!("The token {:?} doesn't have a nud", token),
-        }
+            //function f(x, y) ... end is parsed as the assignment f = function
+            Token::Function => {
+                self.functions += 1;
+                let (name, params, types) = self.parse_signature()?;
+                let body = self.parse_block()?;
+                self.expect(&Token::End)?;
+                self.functions -= 1;
+
+                let function = Function {
+                    name,
+                    params,
+                    types,
+                    slots: 0,
+                    body,
+                };
+                let mut function = AST::Atom(Atom::Function(Rc::new(function)));
+                if self.functions == 0 {
+                    resolver::resolve(&mut function);
+                }
+                AST::Node(Node::new(
+                    Atom::Assign,
+                    vec![AST::Atom(Atom::Symbol(name)), function],
+                ))
+            }
+            Token::While => {
+                let condition = self.parse()?;
+                let block = self.parse_block()?;
+                self.expect(&Token::End)?;
+                AST::Node(Node::new(Atom::While, vec![condition, block]))
+            }
+            //try ... [catch [e] ...] [finally ...] end, the error is only bound to a name
+            //given on the same line as catch. Without a catch block errors are ignored
+            //unless there is a finally block, which runs and then raises them again.
+            Token::Try => {
+                let mut ast = self.parse_block()?;
+                match self.lexer.peek(0)? {
+                    Token::Catch => {
+                        self.lexer.advance()?;
+                        let name = match self.lexer.peek(0)? {
+                            Token::Symbol(name) => {
+                                self.lexer.advance()?;
+                                AST::Atom(Atom::Symbol(intern(name)?))
+                            }
+                            _ => AST::Atom(Atom::Nothing),
+                        };
+                        let handler = self.parse_block()?;
+                        ast = AST::Node(Node::new(Atom::Try, vec![ast, name, handler]));
+                    }
+                    Token::Finally => (),
+                    _ => {
+                        ast = AST::Node(Node::new(
+                            Atom::Try,
+                            vec![ast, AST::Atom(Atom::Nothing), AST::Atom(Atom::Nothing)],
+                        ))
+                    }
+                }
+                if self.lexer.peek(0)? == Token::Finally {
+                    self.lexer.advance()?;
+                    let cleanup = self.parse_block()?;
+                    ast = AST::Node(Node::new(Atom::Finally, vec![ast, cleanup]));
+                }
+                self.expect(&Token::End)?;
+                ast
+            }
+            Token::Struct => self.parse_struct(false)?,
+            Token::Mutable => {
+                self.expect(&Token::Struct)?;
+                self.parse_struct(true)?
+            }
+            //A bare return returns nothing
+            Token::Return => {
+                if self.functions == 0 && !self.top_level_return {
+                    return Err(Error::InvalidSyntax(
+                        "return is only allowed inside a function".to_string(),
+                    ));
+                }
+                let value = match self.lexer.peek(0)? {
+                    Token::NewLine
+                    | Token::Semicolon
+                    | Token::EOF
+                    | Token::End
+                    | Token::Else
+                    | Token::ElseIf
+                    | Token::Catch
+                    | Token::Finally => AST::Atom(Atom::Nothing),
+                    _ => self.parse()?,
+                };
+                AST::Node(Node::new(Atom::Return, vec![value]))
+            }
+            Token::Symbol(value) => AST::Atom(Atom::Symbol(intern(value)?)),
+            _ => return unexpected(format!("The token {:?} doesn't have a nud", token)),
+        };
+        Ok(ast)
     }
 
-    fn led(&mut self, left: AST, token: Token) -> AST {
-        match token {
+    fn led(&mut self, left: AST, token: Token<'a>) -> Result<AST> {
+        let ast = match token {
             Token::Plus
             | Token::Minus
             | Token::Divide
             | Token::Multiply
-            | Token::Assign
             | Token::GreaterThan
             | Token::LessThan
             | Token::Equal
             | Token::NotEqual => {
-                let right = self._parse(token.precedence());
+                let right = self._parse(token.precedence())?;
                 let head = token.to_atom();
-                let node = Node {
-                    head,
-                    tail: vec![left, right],
-                };
+                let node = Node::new(head, vec![left, right]);
                 AST::Node(node)
             }
-            Token::Power => {
-                let right = self._parse(token.precedence() - 1);
+            //Right associative so x = y = 1 and 2 ^ 3 ^ 2 group from the right
+            Token::Power | Token::Assign => {
+                let right = self._parse(token.precedence() - 1)?;
                 let head = token.to_atom();
-                let node = Node {
-                    head,
-                    tail: vec![left, right],
-                };
+                let node = Node::new(head, vec![left, right]);
                 AST::Node(node)
             }
-            token => panic!("The token {:?} has no led implemented", token),
+            Token::LParen => {
+                let mut tail = vec![left];
+                tail.extend(self.parse_list(&Token::RParen)?);
+                AST::Node(Node::new(Atom::Call, tail))
+            }
+            Token::Dot => match self.lexer.advance()? {
+                Token::Symbol(name) => AST::Node(Node::new(Atom::Field(intern(name)?), vec![left])),
+                token => return unexpected(format!("Expected a field name but found {:?}", token)),
+            },
+            Token::LBracket => {
+                let index = self.parse()?;
+                self.expect(&Token::RBracket)?;
+                AST::Node(Node::new(Atom::Index, vec![left, index]))
+            }
+            token => return unexpected(format!("The token {:?} has no led implemented", token)),
+        };
+        Ok(ast)
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<()> {
+        match self.lexer.advance()? {
+            ref token if token == expected => Ok(()),
+            token => unexpected(format!("Expected {:?} but found {:?}", expected, token)),
+        }
+    }
+
+    //Parses the name(params...) of a function definition, each parameter can be annotated
+    //with the name of a type as in x::Float. Any unexpected token is invalid syntax.
+    fn parse_signature(&mut self) -> Result<(Symbol, Vec<Symbol>, Vec<Option<Symbol>>)> {
+        let name = match self.lexer.advance()? {
+            Token::Symbol(name) => intern(name)?,
+            //Operators can have methods defined for structs
+            Token::Plus => Symbol::intern("+"),
+            Token::Minus => Symbol::intern("-"),
+            Token::Multiply => Symbol::intern("*"),
+            Token::Divide => Symbol::intern("/"),
+            Token::Power => Symbol::intern("^"),
+            Token::Equal => Symbol::intern("=="),
+            Token::LessThan => Symbol::intern("<"),
+            token => {
+                return Err(Error::InvalidSyntax(format!(
+                    "Expected a function name but found {:?}",
+                    token
+                )))
+            }
+        };
+        match self.lexer.advance()? {
+            Token::LParen => (),
+            token => {
+                return Err(Error::InvalidSyntax(format!(
+                    "Expected ( after the function name but found {:?}",
+                    token
+                )))
+            }
+        }
+
+        let mut params = vec![];
+        let mut types = vec![];
+        loop {
+            let param = match self.lexer.advance()? {
+                Token::RParen if params.is_empty() => break,
+                Token::Symbol(param) => intern(param)?,
+                token => {
+                    return Err(Error::InvalidSyntax(format!(
+                        "Expected a parameter but found {:?}",
+                        token
+                    )))
+                }
+            };
+            if params.contains(&param) {
+                return Err(Error::InvalidSyntax(format!(
+                    "Duplicate parameter {} in function {}",
+                    param, name
+                )));
+            }
+            let ty = match self.lexer.peek(0)? {
+                Token::DoubleColon => {
+                    self.lexer.advance()?;
+                    match self.lexer.advance()? {
+                        Token::Symbol(ty) => Some(intern(ty)?),
+                        token => {
+                            return Err(Error::InvalidSyntax(format!(
+                                "Expected a type but found {:?}",
+                                token
+                            )))
+                        }
+                    }
+                }
+                _ => None,
+            };
+            params.push(param);
+            types.push(ty);
+
+            match self.lexer.advance()? {
+                Token::Comma => (),
+                Token::RParen => break,
+                token => {
+                    return Err(Error::InvalidSyntax(format!(
+                        "Expected , or ) after a parameter but found {:?}",
+                        token
+                    )))
+                }
+            }
+        }
+        Ok((name, params, types))
+    }
+
+    //struct Name fields... end, with one field name a statement, is parsed as the
+    //assignment Name = struct
+    fn parse_struct(&mut self, mutable: bool) -> Result<AST> {
+        let name = match self.lexer.advance()? {
+            Token::Symbol(name) => intern(name)?,
+            token => return unexpected(format!("Expected a struct name but found {:?}", token)),
+        };
+        let mut fields = vec![];
+        loop {
+            self.skip_terminators()?;
+            let field = match self.lexer.advance()? {
+                Token::End => break,
+                Token::Symbol(field) => intern(field)?,
+                token => return unexpected(format!("Expected a field name but found {:?}", token)),
+            };
+            if fields.contains(&field) {
+                return Err(Error::InvalidSyntax(format!(
+                    "Duplicate field {} in struct {}",
+                    field, name
+                )));
+            }
+            fields.push(field);
+            match self.lexer.peek(0)? {
+                Token::NewLine | Token::Semicolon | Token::End => (),
+                token => {
+                    return unexpected(format!(
+                        "Expected a newline or ; after a field but found {:?}",
+                        token
+                    ))
+                }
+            }
+        }
+
+        let definition = Struct {
+            name,
+            fields,
+            mutable,
+        };
+        Ok(AST::Node(Node::new(
+            Atom::Assign,
+            vec![
+                AST::Atom(Atom::Symbol(name)),
+                AST::Atom(Atom::Struct(Rc::new(definition))),
+            ],
+        )))
+    }
+
+    //Parses comma separated expressions up to and including the closing token
+    fn parse_list(&mut self, close: &Token) -> Result<Vec<AST>> {
+        let mut items = vec![];
+        while self.lexer.peek(0)? != *close {
+            items.push(self.parse()?);
+            if self.lexer.peek(0)? == Token::Comma {
+                self.lexer.advance()?;
+            } else {
+                break;
+            }
         }
+        self.expect(close)?;
+        Ok(items)
     }
 
-    pub fn parse_block(&mut self) -> AST {
+    //Statements are separated by newlines or semicolons, a statement continues onto the
+    //next line when the line ends with a binary operator or inside brackets
+    pub fn parse_block(&mut self) -> Result<AST> {
         let head = Atom::Block;
         let mut tail = vec![];
+        let mut span = Span::default();
 
         loop {
-            match self.lexer.peek(0) {
-                Token::EOF | Token::End | Token::Else | Token::ElseIf => break,
+            self.skip_terminators()?;
+            match self.lexer.peek(0)? {
+                Token::EOF
+                | Token::End
+                | Token::Else
+                | Token::ElseIf
+                | Token::Catch
+                | Token::Finally => break,
                 _ => (),
             }
 
-            let ast = self.parse();
+            if tail.is_empty() {
+                span.start = self.lexer.peek_span(0)?.start;
+            }
+            let ast = self.parse()?;
+            span.end = self.lexer.end();
             tail.push(ast);
+
+            match self.lexer.peek(0)? {
+                Token::NewLine | Token::Semicolon => (),
+                Token::EOF
+                | Token::End
+                | Token::Else
+                | Token::ElseIf
+                | Token::Catch
+                | Token::Finally => break,
+                token => {
+                    return unexpected(format!(
+                        "Expected a newline or ; after a statement but found {:?}",
+                        token
+                    ))
+                }
+            }
         }
-        AST::Node(Node { head, tail })
+        Ok(AST::Node(Node { head, tail, span }))
     }
 
-    pub fn parse(&mut self) -> AST {
+    pub fn parse(&mut self) -> Result<AST> {
         self._parse(0)
     }
 
-    fn _parse(&mut self, precedence: isize) -> AST {
-        let mut token = self.lexer.next();
-        let mut left = self.nud(token);
-        while self.lexer.peek(0).precedence() > precedence {
-            token = self.lexer.next();
-            if token == Token::EOF {
-                break;;
+    fn skip_terminators(&mut self) -> Result<()> {
+        while let Token::NewLine | Token::Semicolon = self.lexer.peek(0)? {
+            self.lexer.advance()?;
+        }
+        Ok(())
+    }
+
+    fn _parse(&mut self, precedence: isize) -> Result<AST> {
+        let depth = self.depth;
+        let result = atom::grow_stack(|| self.parse_expression(precedence));
+        self.depth = depth;
+        result
+    }
+
+    fn nest(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(Error::InvalidSyntax(format!(
+                "Expressions can't be nested more than {} levels deep",
+                MAX_NESTING
+            )));
+        }
+        Ok(())
+    }
+
+    //Operators applied one after another nest like brackets, as 1 + 2 + 3 is (1 + 2) + 3
+    fn parse_expression(&mut self, precedence: isize) -> Result<AST> {
+        self.nest()?;
+        //Allows the operand of a binary operator to start on the next line
+        while self.lexer.peek(0)? == Token::NewLine {
+            self.lexer.advance()?;
+        }
+        let start = self.lexer.peek_span(0)?.start;
+        let mut token = self.lexer.advance()?;
+        let mut left = self.nud(token)?;
+        self.set_span(&mut left, start);
+        while self.lexer.peek(0)?.precedence() > precedence {
+            self.nest()?;
+            token = self.lexer.advance()?;
+            left = self.led(left, token)?;
+            self.set_span(&mut left, start);
+        }
+        Ok(left)
+    }
+
+    //Nodes span from where the expression started to the last token used, a bracketed
+    //expression keeps the span inside the brackets
+    fn set_span(&self, ast: &mut AST, start: usize) {
+        if let AST::Node(node) = ast {
+            if node.span == Span::default() {
+                node.span = Span {
+                    start,
+                    end: self.lexer.end(),
+                };
             }
-            left = self.led(left, token);
         }
-        left
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::atom::Local;
     use crate::lexer::Lexer;
-    use crate::tokenizer::tokenize;
     use indoc::indoc;
 
     #[test]
     fn plus() {
-        let tokens = tokenize("1 + 2").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("1 + 2");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let ans = AST::Node(Node {
-            head: Atom::Plus,
-            tail: vec![AST::Atom(Atom::Float(1.0)), AST::Atom(Atom::Float(2.0))],
-        });
+        let result = parser.parse().unwrap();
+        let ans = AST::Node(Node::new(
+            Atom::Plus,
+            vec![AST::Atom(Atom::Float(1.0)), AST::Atom(Atom::Float(2.0))],
+        ));
         assert_eq!(result, ans);
     }
 
     #[test]
     fn muti() {
-        let tokens = tokenize("3 + 2 * 10").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("3 + 2 * 10");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let n1 = AST::Node(Node {
-            head: Atom::Multiply,
-            tail: vec![AST::Atom(Atom::Float(2.0)), AST::Atom(Atom::Float(10.0))],
-        });
+        let result = parser.parse().unwrap();
+        let n1 = AST::Node(Node::new(
+            Atom::Multiply,
+            vec![AST::Atom(Atom::Float(2.0)), AST::Atom(Atom::Float(10.0))],
+        ));
 
-        let n2 = AST::Node(Node {
-            head: Atom::Plus,
-            tail: vec![AST::Atom(Atom::Float(3.0)), n1],
-        });
+        let n2 = AST::Node(Node::new(Atom::Plus, vec![AST::Atom(Atom::Float(3.0)), n1]));
 
         assert_eq!(result, n2);
     }
 
     #[test]
     fn power() {
-        let tokens = tokenize("3 ^ 2 * 10").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("3 ^ 2 * 10");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let n1 = AST::Node(Node {
-            head: Atom::Power,
-            tail: vec![AST::Atom(Atom::Float(3.0)), AST::Atom(Atom::Float(2.0))],
-        });
+        let result = parser.parse().unwrap();
+        let n1 = AST::Node(Node::new(
+            Atom::Power,
+            vec![AST::Atom(Atom::Float(3.0)), AST::Atom(Atom::Float(2.0))],
+        ));
 
-        let n2 = AST::Node(Node {
-            head: Atom::Multiply,
-            tail: vec![n1, AST::Atom(Atom::Float(10.0))],
-        });
+        let n2 = AST::Node(Node::new(
+            Atom::Multiply,
+            vec![n1, AST::Atom(Atom::Float(10.0))],
+        ));
 
         assert_eq!(result, n2);
     }
 
     #[test]
     fn brackets() {
-        let tokens = tokenize("3 ^ (2 * 10)").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("3 ^ (2 * 10)");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let n1 = AST::Node(Node {
-            head: Atom::Multiply,
-            tail: vec![AST::Atom(Atom::Float(2.0)), AST::Atom(Atom::Float(10.0))],
-        });
+        let result = parser.parse().unwrap();
+        let n1 = AST::Node(Node::new(
+            Atom::Multiply,
+            vec![AST::Atom(Atom::Float(2.0)), AST::Atom(Atom::Float(10.0))],
+        ));
 
-        let n2 = AST::Node(Node {
-            head: Atom::Power,
-            tail: vec![AST::Atom(Atom::Float(3.0)), n1],
-        });
+        let n2 = AST::Node(Node::new(
+            Atom::Power,
+            vec![AST::Atom(Atom::Float(3.0)), n1],
+        ));
 
         assert_eq!(result, n2);
     }
 
     #[test]
     fn assignment() {
-        let tokens = tokenize("x = 10").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("x = 10");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let ans = AST::Node(Node {
-            head: Atom::Assign,
-            tail: vec![
-                AST::Atom(Atom::Symbol("x".to_string())),
+        let result = parser.parse().unwrap();
+        let ans = AST::Node(Node::new(
+            Atom::Assign,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("x"))),
                 AST::Atom(Atom::Float(10.0)),
             ],
-        });
+        ));
 
         assert_eq!(result, ans);
     }
 
     #[test]
     fn equals() {
-        let tokens = tokenize("x == 10").unwrap();
-        let mut lexer = Lexer::new(tokens);
-        println!("LEXER: {:?}", lexer.all());
+        let lexer = Lexer::new("x == 10");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let ans = AST::Node(Node {
-            head: Atom::Equal,
-            tail: vec![
-                AST::Atom(Atom::Symbol("x".to_string())),
+        let result = parser.parse().unwrap();
+        let ans = AST::Node(Node::new(
+            Atom::Equal,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("x"))),
                 AST::Atom(Atom::Float(10.0)),
             ],
-        });
+        ));
 
         assert_eq!(result, ans);
     }
 
     #[test]
     fn not_equals() {
-        let tokens = tokenize("x != 10").unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new("x != 10");
         let mut parser = Parser::new(lexer);
 
-        let result = parser.parse();
-        let ans = AST::Node(Node {
-            head: Atom::NotEqual,
-            tail: vec![
-                AST::Atom(Atom::Symbol("x".to_string())),
+        let result = parser.parse().unwrap();
+        let ans = AST::Node(Node::new(
+            Atom::NotEqual,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("x"))),
                 AST::Atom(Atom::Float(10.0)),
             ],
-        });
+        ));
+
+        assert_eq!(result, ans);
+    }
+
+    #[test]
+    fn assignment_has_lowest_precedence() {
+        let lexer = Lexer::new("x = y = 1 + 2");
+        let mut parser = Parser::new(lexer);
+
+        let result = parser.parse().unwrap();
+        let sum = AST::Node(Node::new(
+            Atom::Plus,
+            vec![AST::Atom(Atom::Float(1.0)), AST::Atom(Atom::Float(2.0))],
+        ));
+        let inner = AST::Node(Node::new(
+            Atom::Assign,
+            vec![AST::Atom(Atom::Symbol(Symbol::intern("y"))), sum],
+        ));
+        let ans = AST::Node(Node::new(
+            Atom::Assign,
+            vec![AST::Atom(Atom::Symbol(Symbol::intern("x"))), inner],
+        ));
+
+        assert_eq!(result, ans);
+    }
+
+    #[test]
+    fn call() {
+        let lexer = Lexer::new(r#"println("a", [1, 2])"#);
+        let mut parser = Parser::new(lexer);
+
+        let result = parser.parse().unwrap();
+        let array = AST::Node(Node::new(
+            Atom::Array,
+            vec![AST::Atom(Atom::Float(1.0)), AST::Atom(Atom::Float(2.0))],
+        ));
+        let ans = AST::Node(Node::new(
+            Atom::Call,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("println"))),
+                AST::Atom(Atom::String("a".to_string())),
+                array,
+            ],
+        ));
+
+        assert_eq!(result, ans);
+    }
+
+    #[test]
+    fn interpolation() {
+        let lexer = Lexer::new(r#""a = $a, sum = $(a + 1)""#);
+        let mut parser = Parser::new(lexer);
+
+        let result = parser.parse().unwrap();
+        let sum = AST::Node(Node::new(
+            Atom::Plus,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("a"))),
+                AST::Atom(Atom::Float(1.0)),
+            ],
+        ));
+        let ans = AST::Node(Node::new(
+            Atom::Interpolate,
+            vec![
+                AST::Atom(Atom::String("a = ".to_string())),
+                AST::Atom(Atom::Symbol(Symbol::intern("a"))),
+                AST::Atom(Atom::String(", sum = ".to_string())),
+                sum,
+            ],
+        ));
+
+        assert_eq!(result, ans);
+    }
+
+    #[test]
+    fn newlines_separate_statements() {
+        let s = indoc!(
+            "
+            x = 1
+            -2; y = 3 +
+                4
+        "
+        );
+
+        let lexer = Lexer::new(s);
+        let mut parser = Parser::new(lexer);
+
+        let n1 = AST::Node(Node::new(
+            Atom::Assign,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("x"))),
+                AST::Atom(Atom::Float(1.0)),
+            ],
+        ));
+        let n2 = AST::Node(Node::new(Atom::Minus, vec![AST::Atom(Atom::Float(2.0))]));
+        let n3 = AST::Node(Node::new(
+            Atom::Assign,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("y"))),
+                AST::Node(Node::new(
+                    Atom::Plus,
+                    vec![AST::Atom(Atom::Float(3.0)), AST::Atom(Atom::Float(4.0))],
+                )),
+            ],
+        ));
+
+        let result = parser.parse_block().unwrap();
+        let ans = AST::Node(Node::new(Atom::Block, vec![n1, n2, n3]));
 
         assert_eq!(result, ans);
     }
 
+    #[test]
+    fn statements_need_a_separator() {
+        let lexer = Lexer::new("x = 1 y = 2");
+        let mut parser = Parser::new(lexer);
+        assert!(parser.parse_block().is_err());
+
+        //A block stops at end but a whole script has to be used up
+        let mut parser = Parser::new(Lexer::new("x = 1\nend"));
+        assert!(parser.parse_program().is_err());
+
+        let mut parser = Parser::new(Lexer::new("f(1 → 2)"));
+        match parser.parse_block() {
+            Err(Error::Lex(lexer::Error::UnknownChar('→'))) => (),
+            res => panic!("Expected a lexing error but got {:?}", res),
+        }
+    }
+
     #[test]
     fn parse_block_simple() {
         let s = indoc!(
@@ -291,32 +851,291 @@ mod tests {
         "
         );
 
-        let tokens = tokenize(s).unwrap();
-        let lexer = Lexer::new(tokens);
+        let lexer = Lexer::new(s);
         let mut parser = Parser::new(lexer);
 
-        let n1 = AST::Node(Node {
-            head: Atom::Assign,
-            tail: vec![
-                AST::Atom(Atom::Symbol("y".to_string())),
+        let n1 = AST::Node(Node::new(
+            Atom::Assign,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("y"))),
                 AST::Atom(Atom::Float(5.0)),
             ],
-        });
+        ));
 
-        let n2 = AST::Node(Node {
-            head: Atom::Multiply,
-            tail: vec![
-                AST::Atom(Atom::Symbol("y".to_string())),
+        let n2 = AST::Node(Node::new(
+            Atom::Multiply,
+            vec![
+                AST::Atom(Atom::Symbol(Symbol::intern("y"))),
                 AST::Atom(Atom::Float(8.0)),
             ],
-        });
+        ));
+
+        let result = parser.parse_block().unwrap();
+        let ans = AST::Node(Node::new(Atom::Block, vec![n1, n2]));
+
+        assert_eq!(result, ans);
+    }
+
+    #[test]
+    fn function_definition() {
+        let s = indoc!(
+            "
+            function add(a, b)
+                a + b
+            end
+        "
+        );
+        let result = Parser::new(Lexer::new(s)).parse_block().unwrap();
+        let local = |name: &str, slot| {
+            let name = Symbol::intern(name);
+            AST::Atom(Atom::Local(Local {
+                name,
+                depth: 0,
+                slot,
+            }))
+        };
+        let function = Function {
+            name: Symbol::intern("add"),
+            params: vec![Symbol::intern("a"), Symbol::intern("b")],
+            types: vec![None, None],
+            slots: 2,
+            body: AST::Node(Node::new(
+                Atom::Block,
+                vec![AST::Node(Node::new(
+                    Atom::Plus,
+                    vec![local("a", 0), local("b", 1)],
+                ))],
+            )),
+        };
+        let ans = AST::Node(Node::new(
+            Atom::Block,
+            vec![AST::Node(Node::new(
+                Atom::Assign,
+                vec![
+                    AST::Atom(Atom::Symbol(Symbol::intern("add"))),
+                    AST::Atom(Atom::Function(Rc::new(function))),
+                ],
+            ))],
+        ));
+        assert_eq!(result, ans);
+
+        for case in [
+            "function f(1) end",
+            "function f(x, x) end",
+            "function f end",
+        ]
+        .iter()
+        {
+            match Parser::new(Lexer::new(case)).parse_block() {
+                Err(Error::InvalidSyntax(_)) => (),
+                res => panic!("Expected {} to be invalid but got {:?}", case, res),
+            }
+        }
+    }
+
+    #[test]
+    fn try_catch_finally() {
+        let s = indoc!(
+            "
+            try
+                a
+            catch e
+                b
+            finally
+                c
+            end
+            try a catch; b end
+            try a finally c end
+            try a end
+        "
+        );
+        let result = Parser::new(Lexer::new(s)).parse_block().unwrap();
+        let symbol = |s| AST::Atom(Atom::Symbol(Symbol::intern(s)));
+        let block = |s| AST::Node(Node::new(Atom::Block, vec![symbol(s)]));
+        let node = |head, tail| AST::Node(Node::new(head, tail));
+        let nothing = || AST::Atom(Atom::Nothing);
+
+        let ans = node(
+            Atom::Block,
+            vec![
+                node(
+                    Atom::Finally,
+                    vec![
+                        node(Atom::Try, vec![block("a"), symbol("e"), block("b")]),
+                        block("c"),
+                    ],
+                ),
+                node(Atom::Try, vec![block("a"), nothing(), block("b")]),
+                node(Atom::Finally, vec![block("a"), block("c")]),
+                node(Atom::Try, vec![block("a"), nothing(), nothing()]),
+            ],
+        );
+        assert_eq!(result, ans);
+        assert!(Parser::new(Lexer::new("try a catch e b"))
+            .parse_block()
+            .is_err());
+    }
 
-        let result = parser.parse_block();
-        let ans = AST::Node(Node {
-            head: Atom::Block,
-            tail: vec![n1, n2],
-        });
+    #[test]
+    fn return_statements() {
+        let result = Parser::new(Lexer::new("return\nreturn 1 + 2")).parse_block();
+        let node = |head, tail| AST::Node(Node::new(head, tail));
+        let sum = node(
+            Atom::Plus,
+            vec![AST::Atom(Atom::Float(1.0)), AST::Atom(Atom::Float(2.0))],
+        );
+        let ans = node(
+            Atom::Block,
+            vec![
+                node(Atom::Return, vec![AST::Atom(Atom::Nothing)]),
+                node(Atom::Return, vec![sum]),
+            ],
+        );
+        assert_eq!(result, Ok(ans));
+
+        let mut parser = Parser::new(Lexer::new("function f() return end; return 1"));
+        parser.set_top_level_return(false);
+        match parser.parse_program() {
+            Err(Error::InvalidSyntax(msg)) => {
+                assert_eq!(msg, "return is only allowed inside a function")
+            }
+            res => panic!("Expected a syntax error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn structs_and_fields() {
+        let s = indoc!(
+            "
+            mutable struct Point
+                x; y
+            end
+            p.x.y = a.b
+        "
+        );
+        let result = Parser::new(Lexer::new(s)).parse_block().unwrap();
+        let symbol = |s| Symbol::intern(s);
+        let node = |head, tail| AST::Node(Node::new(head, tail));
+        let field = |target, name| node(Atom::Field(symbol(name)), vec![target]);
+        let point = Struct {
+            name: symbol("Point"),
+            fields: vec![symbol("x"), symbol("y")],
+            mutable: true,
+        };
 
+        let ans = node(
+            Atom::Block,
+            vec![
+                node(
+                    Atom::Assign,
+                    vec![
+                        AST::Atom(Atom::Symbol(symbol("Point"))),
+                        AST::Atom(Atom::Struct(Rc::new(point))),
+                    ],
+                ),
+                node(
+                    Atom::Assign,
+                    vec![
+                        field(field(AST::Atom(Atom::Symbol(symbol("p"))), "x"), "y"),
+                        field(AST::Atom(Atom::Symbol(symbol("a"))), "b"),
+                    ],
+                ),
+            ],
+        );
         assert_eq!(result, ans);
+
+        for s in [
+            "struct P x; x end",
+            "struct P x y end",
+            "mutable P end",
+            "p.1",
+        ]
+        .iter()
+        {
+            match Parser::new(Lexer::new(s)).parse_block() {
+                Err(_) => (),
+                res => panic!("Expected {} to fail but got {:?}", s, res),
+            }
+        }
+    }
+
+    #[test]
+    fn type_annotations() {
+        let ast = Parser::new(Lexer::new("function f(x::Float, y, z::Point) end"))
+            .parse()
+            .unwrap();
+        let function = match ast {
+            AST::Node(Node { tail, .. }) => match &tail[1] {
+                AST::Atom(Atom::Function(function)) => function.clone(),
+                ast => panic!("Expected a function but got {:?}", ast),
+            },
+            ast => panic!("Expected a function definition but got {:?}", ast),
+        };
+        let types = vec![
+            Some(Symbol::intern("Float")),
+            None,
+            Some(Symbol::intern("Point")),
+        ];
+        assert_eq!(function.types, types);
+        assert_eq!(function.signature(), "f(x::Float, y, z::Point)");
+
+        let ast = Parser::new(Lexer::new("function ==(a::Vec2, b) end"))
+            .parse()
+            .unwrap();
+        match ast {
+            AST::Node(Node { tail, .. }) => match &tail[1] {
+                AST::Atom(Atom::Function(function)) => {
+                    assert_eq!(function.signature(), "==(a::Vec2, b)")
+                }
+                ast => panic!("Expected a function but got {:?}", ast),
+            },
+            ast => panic!("Expected a function definition but got {:?}", ast),
+        }
+
+        for s in [
+            "function f(x::) end",
+            "function f(x::1) end",
+            "function f(::Float) end",
+        ]
+        .iter()
+        {
+            match Parser::new(Lexer::new(s)).parse_block() {
+                Err(Error::InvalidSyntax(_)) => (),
+                res => panic!("Expected {} to be invalid but got {:?}", s, res),
+            }
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let n = MAX_NESTING / 2;
+        let cases = [
+            format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}1", "1 + ".repeat(n / 2)),
+            format!("{}1{}", "if true then ".repeat(n / 4), " end".repeat(n / 4)),
+        ];
+        for s in cases.iter() {
+            assert!(Parser::new(Lexer::new(s)).parse_program().is_ok());
+        }
+
+        let n = 100_000;
+        let cases = [
+            format!("{}1{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}1", "1 + ".repeat(n)),
+            format!("{}1", "- ".repeat(n)),
+            format!("{}1{}", "if true then ".repeat(n), " end".repeat(n)),
+            format!("f{}", "()".repeat(n)),
+        ];
+        for s in cases.iter() {
+            match Parser::new(Lexer::new(s)).parse_program() {
+                Err(Error::InvalidSyntax(msg)) => {
+                    assert_eq!(
+                        msg,
+                        "Expressions can't be nested more than 1000 levels deep"
+                    )
+                }
+                res => panic!("Expected the nesting to be too deep but got {:?}", res),
+            }
+        }
     }
 }
@@ -0,0 +1,165 @@
+use crate::atom::{self, Atom, Local, AST};
+use crate::symbol::Symbol;
+use std::rc::Rc;
+
+//Rewrites the variables inside function bodies into frame slots so they can be read
+//without a lookup by name. Like Julia, a variable assigned anywhere in a function is
+//local to it unless it's already a local of an enclosing function, in which case it
+//refers to that variable. Everything else, including all top level variables, stays a
+//global and is looked up by symbol.
+pub fn resolve(ast: &mut AST) {
+    Resolver { scopes: vec![] }.resolve(ast);
+}
+
+struct Resolver {
+    //The locals of each enclosing function, innermost last
+    scopes: Vec<Vec<Symbol>>,
+}
+
+impl Resolver {
+    fn resolve(&mut self, ast: &mut AST) {
+        atom::grow_stack(|| self.resolve_node(ast))
+    }
+
+    fn resolve_node(&mut self, ast: &mut AST) {
+        match ast {
+            AST::Atom(Atom::Symbol(name)) => {
+                if let Some(local) = self.find(*name) {
+                    *ast = AST::Atom(Atom::Local(local));
+                }
+            }
+            AST::Atom(Atom::Function(function)) => {
+                let function = Rc::get_mut(function)
+                    .expect("Functions are resolved before they can be shared");
+                let mut locals = function.params.clone();
+                self.declare(&function.body, &mut locals);
+
+                self.scopes.push(locals);
+                self.resolve(&mut function.body);
+                function.slots = self.scopes.pop().map_or(0, |locals| locals.len());
+            }
+            AST::Node(node) => {
+                for ast in node.tail.iter_mut() {
+                    self.resolve(ast);
+                }
+            }
+            AST::Atom(_) => (),
+        }
+    }
+
+    //Collects the variables assigned in a function body, nested functions have their own
+    //scope so they are skipped
+    fn declare(&self, ast: &AST, locals: &mut Vec<Symbol>) {
+        if let AST::Node(node) = ast {
+            //Assignments and catch blocks introduce variables
+            let name = match (&node.head, node.tail.as_slice()) {
+                (Atom::Assign, [AST::Atom(Atom::Symbol(name)), ..]) => Some(name),
+                (Atom::Try, [_, AST::Atom(Atom::Symbol(name)), ..]) => Some(name),
+                _ => None,
+            };
+            if let Some(name) = name {
+                if !locals.contains(name) && self.find(*name).is_none() {
+                    locals.push(*name);
+                }
+            }
+            for ast in node.tail.iter() {
+                self.declare(ast, locals);
+            }
+        }
+    }
+
+    fn find(&self, name: Symbol) -> Option<Local> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, locals)| {
+                let slot = locals.iter().position(|&local| local == name)?;
+                Some(Local { name, depth, slot })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atom::{Function, Node};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use indoc::indoc;
+
+    fn function(ast: &AST) -> &Function {
+        match ast {
+            AST::Node(Node { head, tail, .. }) if *head == Atom::Assign => match &tail[1] {
+                AST::Atom(Atom::Function(function)) => function,
+                ast => panic!("Expected a function but got {:?}", ast),
+            },
+            ast => panic!("Expected a function definition but got {:?}", ast),
+        }
+    }
+
+    fn local(name: &str, depth: usize, slot: usize) -> AST {
+        let name = Symbol::intern(name);
+        AST::Atom(Atom::Local(Local { name, depth, slot }))
+    }
+
+    #[test]
+    fn locals_and_captures() {
+        let s = indoc!(
+            "
+            function counter(step)
+                count = 0
+                function next()
+                    count = count + step
+                    total = count
+                end
+            end
+        "
+        );
+        let ast = Parser::new(Lexer::new(s)).parse().unwrap();
+        let outer = function(&ast);
+        assert_eq!(outer.slots, 3);
+
+        let statements = match &outer.body {
+            AST::Node(node) => &node.tail,
+            ast => panic!("Expected a block but got {:?}", ast),
+        };
+        let inner = function(&statements[1]);
+        assert_eq!(inner.slots, 1);
+
+        //count and step are captured from the enclosing frame, total is a new local
+        let sum = AST::Node(Node::new(
+            Atom::Plus,
+            vec![local("count", 1, 1), local("step", 1, 0)],
+        ));
+        let body = AST::Node(Node::new(
+            Atom::Block,
+            vec![
+                AST::Node(Node::new(Atom::Assign, vec![local("count", 1, 1), sum])),
+                AST::Node(Node::new(
+                    Atom::Assign,
+                    vec![local("total", 0, 0), local("count", 1, 1)],
+                )),
+            ],
+        ));
+        assert_eq!(inner.body, body);
+    }
+
+    #[test]
+    fn globals_stay_symbols() {
+        let ast = Parser::new(Lexer::new("function f(x) x + y end"))
+            .parse()
+            .unwrap();
+        let body = AST::Node(Node::new(
+            Atom::Block,
+            vec![AST::Node(Node::new(
+                Atom::Plus,
+                vec![
+                    local("x", 0, 0),
+                    AST::Atom(Atom::Symbol(Symbol::intern("y"))),
+                ],
+            ))],
+        ));
+        assert_eq!(function(&ast).body, body);
+    }
+}
@@ -0,0 +1,81 @@
+use super::{define, expect_args};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn load(env: &mut Enviroment) {
+    env.insert("nothing", Object::Nothing);
+    define(env, "show", show);
+    define(env, "print", print);
+    define(env, "println", println);
+    define(env, "repr", repr);
+    define(env, "string", string);
+    define(env, "time", time);
+    define(env, "throw", throw);
+    define(env, "error", error);
+}
+
+fn write_stdout(s: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(s.as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(|e| Error::IOError(e.to_string()))
+}
+
+//Writes the parseable representation of a single value
+fn show(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("show", args, 1)?;
+    write_stdout(&interpreter.print(&args[0], true)?)?;
+    Ok(Object::Nothing)
+}
+
+fn print(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    write_stdout(&concat(interpreter, args)?)?;
+    Ok(Object::Nothing)
+}
+
+fn println(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    write_stdout(&(concat(interpreter, args)? + "\n"))?;
+    Ok(Object::Nothing)
+}
+
+fn repr(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("repr", args, 1)?;
+    Ok(Object::String(interpreter.print(&args[0], true)?))
+}
+
+fn string(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    Ok(Object::String(concat(interpreter, args)?))
+}
+
+//Seconds since the unix epoch
+fn time(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("time", args, 0)?;
+    interpreter.capabilities().check_clock()?;
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::IOError(e.to_string()))?;
+    Ok(Object::Float(elapsed.as_secs_f64()))
+}
+
+//Raises any value, a caught error is raised again as it was
+fn throw(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("throw", args, 1)?;
+    Err(interpreter.throw(args[0].clone()))
+}
+
+//error(msg...) raises an ErrorException with the arguments joined as its message
+fn error(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    Err(Error::ErrorException(concat(interpreter, args)?))
+}
+
+fn concat(interpreter: &mut Interpreter, args: &[Object]) -> Result<String> {
+    let mut s = String::new();
+    for arg in args.iter() {
+        interpreter.print_to(&mut s, arg, false)?;
+    }
+    Ok(s)
+}
@@ -0,0 +1,491 @@
+use super::{define, expect_string};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+
+pub fn load(env: &mut Enviroment) {
+    define(env, "format", format);
+}
+
+//format(template, args...) fills each {} in the template with the next argument.
+//A replacement field is written {[arg][:spec]} where arg is a 0 based position or the name
+//of a global variable, and spec is [[fill]align][sign][#][0][width][.precision][type].
+//Locals are resolved to frame slots before the script runs so they can't be named, pass
+//them as arguments instead. Literal braces are written {{ and }}.
+fn format(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    if args.is_empty() {
+        return Err(Error::ArgumentError(
+            "format expects a template string".to_string(),
+        ));
+    }
+    let template = expect_string("format", &args[0])?;
+    let args = &args[1..];
+
+    let mut out = String::new();
+    let mut used = vec![false; args.len()];
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '}' => return Err(format_error("unmatched } in template")),
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(format_error("unclosed { in template")),
+                    }
+                }
+
+                let (arg, spec) = match field.find(':') {
+                    Some(i) => (&field[..i], &field[i + 1..]),
+                    None => (field.as_str(), ""),
+                };
+                let spec = Spec::parse(spec)?;
+
+                let value = if arg.is_empty() {
+                    let i = next;
+                    next += 1;
+                    positional(args, &mut used, i)?
+                } else if let Ok(i) = arg.parse::<usize>() {
+                    positional(args, &mut used, i)?
+                } else {
+                    match interpreter.get(arg) {
+                        Some(value) => value.clone(),
+                        None => return Err(Error::UndefinedVariable(arg.to_string())),
+                    }
+                };
+                //Padding and precision can make the field far larger than the value, so
+                //the most it could take is checked up front like strings::repeat
+                interpreter.reserve(out.len().saturating_add(spec.size(&value)))?;
+                spec.write(interpreter, &mut out, &value)?;
+            }
+            c => out.push(c),
+        }
+    }
+
+    if let Some(i) = used.iter().position(|used| !used) {
+        return Err(format_error(&format!("argument {} is never used", i)));
+    }
+    Ok(Object::String(out))
+}
+
+fn positional(args: &[Object], used: &mut [bool], i: usize) -> Result<Object> {
+    match args.get(i) {
+        Some(value) => {
+            used[i] = true;
+            Ok(value.clone())
+        }
+        None => Err(format_error(&format!(
+            "argument {} is missing, {} were given",
+            i,
+            args.len()
+        ))),
+    }
+}
+
+fn format_error(msg: &str) -> Error {
+    Error::FormatError(msg.to_string())
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, PartialEq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: char,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Result<Self> {
+        let chars: Vec<char> = spec.chars().collect();
+        let mut idx = 0;
+        let mut result = Spec {
+            fill: ' ',
+            align: None,
+            plus: false,
+            space: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            kind: ' ',
+        };
+
+        let align = |c: Option<&char>| match c {
+            Some('<') => Some(Align::Left),
+            Some('>') => Some(Align::Right),
+            Some('^') => Some(Align::Center),
+            _ => None,
+        };
+        if let Some(a) = align(chars.get(1)) {
+            result.fill = chars[0];
+            result.align = Some(a);
+            idx = 2;
+        } else if let Some(a) = align(chars.first()) {
+            result.align = Some(a);
+            idx = 1;
+        }
+
+        match chars.get(idx) {
+            Some('+') => {
+                result.plus = true;
+                idx += 1;
+            }
+            Some(' ') => {
+                result.space = true;
+                idx += 1;
+            }
+            Some('-') => idx += 1,
+            _ => (),
+        }
+        if chars.get(idx) == Some(&'#') {
+            result.alternate = true;
+            idx += 1;
+        }
+        if chars.get(idx) == Some(&'0') {
+            result.zero = true;
+            idx += 1;
+        }
+
+        let (width, next) = parse_number(&chars, idx, spec)?;
+        result.width = width.unwrap_or(0);
+        idx = next;
+        if chars.get(idx) == Some(&'.') {
+            let (precision, next) = parse_number(&chars, idx + 1, spec)?;
+            if precision.is_none() {
+                return Err(format_error(&format!("missing precision in {{:{}}}", spec)));
+            }
+            result.precision = precision;
+            idx = next;
+        }
+
+        match &chars[idx..] {
+            [] => (),
+            [kind] if "?sdfeExXob".contains(*kind) => result.kind = *kind,
+            _ => return Err(format_error(&format!("invalid format spec {{:{}}}", spec))),
+        }
+        Ok(result)
+    }
+
+    //The bytes the padding and precision could add to the formatted value
+    fn size(&self, value: &Object) -> usize {
+        let padding = self.width.saturating_mul(self.fill.len_utf8());
+        match value {
+            Object::Float(_) if self.kind != '?' && self.kind != 's' => {
+                padding.saturating_add(self.precision.unwrap_or(0))
+            }
+            _ => padding,
+        }
+    }
+
+    fn write(&self, interpreter: &mut Interpreter, out: &mut String, value: &Object) -> Result<()> {
+        let x = match value {
+            Object::Float(x) if self.kind != '?' && self.kind != 's' => *x,
+            _ => return self.write_text(interpreter, out, value),
+        };
+
+        let digits = match self.kind {
+            ' ' => match self.precision {
+                Some(precision) if x.is_finite() => format!("{:.*}", precision, x.abs()),
+                _ => Object::Float(x.abs()).to_string(),
+            },
+            'f' if x.is_finite() => format!("{:.*}", self.precision.unwrap_or(6), x.abs()),
+            'e' | 'E' if x.is_finite() => {
+                let s = scientific(x.abs(), self.precision.unwrap_or(6));
+                if self.kind == 'E' {
+                    s.to_uppercase()
+                } else {
+                    s
+                }
+            }
+            'd' | 'x' | 'X' | 'o' | 'b' => self.integer(x)?,
+            _ => Object::Float(x.abs()).to_string(),
+        };
+
+        let sign = if x.is_sign_negative() && !x.is_nan() {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        };
+        let prefix = match self.kind {
+            'x' | 'X' if self.alternate => "0x",
+            'o' if self.alternate => "0o",
+            'b' if self.alternate => "0b",
+            _ => "",
+        };
+
+        //Zero padding goes between the sign and the digits and ignores the alignment
+        if self.zero && x.is_finite() {
+            let len = sign.len() + prefix.len() + digits.chars().count();
+            out.push_str(sign);
+            out.push_str(prefix);
+            out.extend(std::iter::repeat_n('0', self.width.saturating_sub(len)));
+            out.push_str(&digits);
+        } else {
+            let s = format!("{}{}{}", sign, prefix, digits);
+            self.pad(out, &s, Align::Right);
+        }
+        Ok(())
+    }
+
+    //Writes a non numeric value, or a number formatted with s or ?
+    fn write_text(
+        &self,
+        interpreter: &mut Interpreter,
+        out: &mut String,
+        value: &Object,
+    ) -> Result<()> {
+        let s = match self.kind {
+            '?' => interpreter.print(value, true)?,
+            ' ' | 's' => interpreter.print(value, false)?,
+            kind => {
+                return Err(format_error(&format!(
+                    "{{:{}}} expects a number but was given {}",
+                    kind,
+                    value.repr()
+                )))
+            }
+        };
+        let s = match self.precision {
+            Some(precision) => s.chars().take(precision).collect(),
+            None => s,
+        };
+        self.pad(out, &s, Align::Left);
+        Ok(())
+    }
+
+    fn integer(&self, x: f64) -> Result<String> {
+        //u64::MAX rounds up to 2^64 as a float, which is already too large to cast
+        if x.fract() != 0.0 || !x.is_finite() || x.abs() >= u64::MAX as f64 {
+            return Err(format_error(&format!(
+                "{{:{}}} expects an integer but was given {}",
+                self.kind,
+                Object::Float(x)
+            )));
+        }
+        let n = x.abs() as u64;
+        Ok(match self.kind {
+            'x' => format!("{:x}", n),
+            'X' => format!("{:X}", n),
+            'o' => format!("{:o}", n),
+            'b' => format!("{:b}", n),
+            _ => n.to_string(),
+        })
+    }
+
+    fn pad(&self, out: &mut String, s: &str, default: Align) {
+        let padding = self.width.saturating_sub(s.chars().count());
+        let (left, right) = match self.align.unwrap_or(default) {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+        out.extend(std::iter::repeat_n(self.fill, left));
+        out.push_str(s);
+        out.extend(std::iter::repeat_n(self.fill, right));
+    }
+}
+
+//Reads a run of digits starting at idx, returning the number and the index after it
+//Reads the digits at idx, returning None if there are none
+fn parse_number(chars: &[char], mut idx: usize, spec: &str) -> Result<(Option<usize>, usize)> {
+    let start = idx;
+    while idx < chars.len() && chars[idx].is_ascii_digit() {
+        idx += 1;
+    }
+    if start == idx {
+        return Ok((None, idx));
+    }
+    let number: String = chars[start..idx].iter().collect();
+    match number.parse() {
+        Ok(n) => Ok((Some(n), idx)),
+        Err(_) => Err(format_error(&format!(
+            "{} is too large in {{:{}}}",
+            number, spec
+        ))),
+    }
+}
+
+//Formats like C's %e, with a signed exponent of at least two digits such as 1.50e+03
+fn scientific(x: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = s.split_at(s.find('e').expect("Scientific notation has an e"));
+    let exponent: i32 = exponent[1..].parse().expect("Exponent is an integer");
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::eval;
+    use super::*;
+    use indoc::indoc;
+
+    fn string(s: &str) -> String {
+        match eval(s) {
+            Ok(Object::String(value)) => value,
+            res => panic!("Expected {} to evaluate to a string but got {:?}", s, res),
+        }
+    }
+
+    #[test]
+    fn alignment_and_precision() {
+        assert_eq!(
+            string(r#"format("{:>8.2f} {}", 3.14159, "pie")"#),
+            "    3.14 pie"
+        );
+        assert_eq!(string(r#"format("[{:<6}]", "ab")"#), "[ab    ]");
+        assert_eq!(string(r#"format("[{:*^7}]", "ab")"#), "[**ab***]");
+        assert_eq!(string(r#"format("[{:5}]", 42)"#), "[   42]");
+        assert_eq!(string(r#"format("{:.3}", "abcdef")"#), "abc");
+        assert_eq!(string(r#"format("{:.1}", 2)"#), "2.0");
+    }
+
+    #[test]
+    fn signs_and_padding() {
+        assert_eq!(string(r#"format("{:+} {:+}", 5, -5)"#), "+5 -5");
+        assert_eq!(string(r#"format("{:08.3f}", -1.5)"#), "-001.500");
+        assert_eq!(string(r#"format("{: d}", 7)"#), " 7");
+        assert_eq!(string(r#"format("{:+05}", 42)"#), "+0042");
+    }
+
+    #[test]
+    fn integer_bases_and_scientific() {
+        assert_eq!(string(r#"format("{:x} {:#X}", 255, 255)"#), "ff 0xFF");
+        assert_eq!(string(r#"format("{:#o} {:b}", 8, 5)"#), "0o10 101");
+        assert_eq!(string(r#"format("{:#010b}", 5)"#), "0b00000101");
+        assert_eq!(string(r#"format("{:e}", 1234.5)"#), "1.234500e+03");
+        assert_eq!(string(r#"format("{:.2E}", 0.00012)"#), "1.20E-04");
+        assert_eq!(string(r#"format("{:f}", Inf)"#), "Inf");
+    }
+
+    #[test]
+    fn positional_and_named() {
+        assert_eq!(string(r#"format("{1} {0} {1}", "a", "b")"#), "b a b");
+        assert_eq!(
+            string(
+                r#"name = "lemur"
+                format("{name:?} {{literal}} {}", 1)"#
+            ),
+            r#""lemur" {literal} 1"#
+        );
+
+        //Names are looked up as globals, even inside a function
+        let s = indoc!(
+            r#"
+            x = "global"
+            function f()
+                x = "local"
+                format("{x} {}", x)
+            end
+            f()
+        "#
+        );
+        assert_eq!(string(s), "global local");
+        let s = indoc!(
+            r#"
+            function g()
+                y = 1
+                format("{y}")
+            end
+            g()
+        "#
+        );
+        assert_eq!(eval(s), Err(Error::UndefinedVariable("y".to_string())));
+    }
+
+    #[test]
+    fn format_errors() {
+        let cases = [
+            r#"format("{", 1)"#,
+            r#"format("}")"#,
+            r#"format("{} {}", 1)"#,
+            r#"format("{}", 1, 2)"#,
+            r#"format("{:d}", 1.5)"#,
+            r#"format("{:x}", "a")"#,
+            r#"format("{:z}", 1)"#,
+            r#"format("{:.}", 1)"#,
+        ];
+        for case in cases.iter() {
+            match eval(case) {
+                Err(Error::FormatError(_)) => (),
+                res => panic!("Expected {} to be a format error but got {:?}", case, res),
+            }
+        }
+        //2^64 is the first integer that doesn't fit, widths and precisions have to fit a usize
+        for (case, msg) in [
+            (r#"format("{:d}", 2.0^64)"#, "expects an integer"),
+            (r#"format("{:x}", -(2.0^64))"#, "expects an integer"),
+            (r#"format("{:99999999999999999999}", 1)"#, "is too large"),
+            (r#"format("{:.99999999999999999999}", 1)"#, "is too large"),
+        ]
+        .iter()
+        {
+            match eval(case) {
+                Err(Error::FormatError(res)) => assert!(res.contains(msg), "{}", res),
+                res => panic!("Expected {} to be a format error but got {:?}", case, res),
+            }
+        }
+        assert_eq!(string(r#"format("{:d}", 2.0^63)"#), "9223372036854775808");
+
+        match eval(r#"format("{missing}")"#) {
+            Err(Error::UndefinedVariable(_)) => (),
+            res => panic!("Expected an undefined variable error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn large_fields_count_towards_the_memory_limit() {
+        use crate::interpreter::Backend;
+        use crate::lexer::Lexer;
+        use crate::parser::Parser;
+
+        let cases = [
+            r#"format("{:9999999999}", 1)"#,
+            r#"format("{:.9999999999}", 1)"#,
+            r#"format("{:.9999999999e}", 1)"#,
+            r#"format("{:x<9999999999}", "a")"#,
+        ];
+        for case in cases.iter() {
+            let ast = Parser::new(Lexer::new(case)).parse_program().unwrap();
+            for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+                let mut interpreter = Interpreter::new(Enviroment::new());
+                interpreter.set_backend(*backend);
+                interpreter.set_memory_limit(Some(10_000));
+                match interpreter.run(&ast) {
+                    Err(Error::OutOfMemory(_)) => (),
+                    res => panic!("Expected {} to run out of memory but got {:?}", case, res),
+                }
+            }
+        }
+        //Precision only shortens text
+        assert_eq!(string(r#"format("{:.9999999999}", "ab")"#), "ab");
+    }
+}
@@ -0,0 +1,444 @@
+use super::{define, expect_args, expect_args_between, expect_string};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::{FileHandle, Object};
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+//Paths are checked against the interpreter's capabilities before they are touched, a
+//handle returned by open can be used however it was opened
+pub fn load(env: &mut Enviroment) {
+    define(env, "read", read);
+    define(env, "readlines", readlines);
+    define(env, "write", write);
+    define(env, "open", open);
+    define(env, "readline", readline);
+    define(env, "eof", eof);
+    define(env, "close", close);
+    define(env, "isfile", isfile);
+    define(env, "isdir", isdir);
+    define(env, "readdir", readdir);
+    define(env, "mkdir", mkdir);
+    define(env, "rm", rm);
+    define(env, "joinpath", joinpath);
+}
+
+fn io_error(path: &str, error: io::Error) -> Error {
+    Error::IOError(format!("{}: {}", path, error))
+}
+
+fn readable(interpreter: &Interpreter, name: &str, arg: &Object) -> Result<(String, PathBuf)> {
+    let path = expect_string(name, arg)?;
+    let checked = interpreter.check_read(Path::new(path))?;
+    Ok((path.to_string(), checked))
+}
+
+fn writable(interpreter: &Interpreter, name: &str, arg: &Object) -> Result<(String, PathBuf)> {
+    let path = expect_string(name, arg)?;
+    let checked = interpreter.check_write(Path::new(path))?;
+    Ok((path.to_string(), checked))
+}
+
+fn expect_handle<'a>(name: &str, arg: &'a Object) -> Result<&'a FileHandle> {
+    match arg {
+        Object::File(handle) => Ok(handle),
+        o => Err(Error::TypeError(format!(
+            "{} expects a file but was given {}",
+            name,
+            o.repr()
+        ))),
+    }
+}
+
+//Runs `f` with the open file, or fails if it has been closed
+fn with_file<T>(
+    handle: &FileHandle,
+    f: impl FnOnce(&mut BufReader<File>) -> io::Result<T>,
+) -> Result<T> {
+    let path = handle.path.display().to_string();
+    match handle.file.borrow_mut().as_mut() {
+        Some(file) => f(file).map_err(|e| io_error(&path, e)),
+        None => Err(Error::IOError(format!("{}: the file is closed", path))),
+    }
+}
+
+fn read(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("read", args, 1)?;
+    Ok(Object::String(read_file(interpreter, "read", &args[0])?))
+}
+
+fn readlines(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("readlines", args, 1)?;
+    let s = read_file(interpreter, "readlines", &args[0])?;
+    Ok(Object::array(
+        s.lines()
+            .map(|line| Object::String(line.to_string()))
+            .collect(),
+    ))
+}
+
+fn read_file(interpreter: &mut Interpreter, name: &str, arg: &Object) -> Result<String> {
+    let (path, checked) = readable(interpreter, name, arg)?;
+    //The file could be larger than the memory the script is allowed
+    let size = fs::metadata(&checked)
+        .map_err(|e| io_error(&path, e))?
+        .len();
+    interpreter.reserve(size as usize)?;
+    fs::read_to_string(&checked).map_err(|e| io_error(&path, e))
+}
+
+//write(path, value) replaces the file's contents, write(file, value) writes to an open
+//file. Both return the number of bytes written.
+fn write(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("write", args, 2)?;
+    let s = interpreter.print(&args[1], false)?;
+    match &args[0] {
+        Object::File(handle) => with_file(handle, |file| file.get_mut().write_all(s.as_bytes()))?,
+        _ => {
+            let (path, checked) = writable(interpreter, "write", &args[0])?;
+            fs::write(&checked, &s).map_err(|e| io_error(&path, e))?
+        }
+    }
+    Ok(Object::Float(s.len() as f64))
+}
+
+//open(path, mode) where mode is "r" to read, which is the default, "w" to write or "a"
+//to append
+fn open(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("open", args, 1, 2)?;
+    let mode = match args.get(1) {
+        Some(mode) => expect_string("open", mode)?,
+        None => "r",
+    };
+    let mut options = OpenOptions::new();
+    let (path, checked) = match mode {
+        "r" => {
+            options.read(true);
+            readable(interpreter, "open", &args[0])?
+        }
+        "w" => {
+            options.write(true).create(true).truncate(true);
+            writable(interpreter, "open", &args[0])?
+        }
+        "a" => {
+            options.append(true).create(true);
+            writable(interpreter, "open", &args[0])?
+        }
+        mode => {
+            return Err(Error::ArgumentError(format!(
+                "open expects the mode \"r\", \"w\" or \"a\" but was given {:?}",
+                mode
+            )))
+        }
+    };
+
+    let file = options.open(&checked).map_err(|e| io_error(&path, e))?;
+    Ok(Object::File(Rc::new(FileHandle {
+        path: PathBuf::from(path),
+        file: RefCell::new(Some(BufReader::new(file))),
+    })))
+}
+
+//The next line without its line ending, or an empty string at the end of the file
+fn readline(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("readline", args, 1)?;
+    let handle = expect_handle("readline", &args[0])?;
+    //Read a buffer at a time so a line longer than the memory limit is never held
+    let mut bytes = vec![];
+    loop {
+        let (chunk, done) = with_file(handle, |file| {
+            let buffer = file.fill_buf()?;
+            let (chunk, done) = match buffer.iter().position(|&b| b == b'\n') {
+                Some(i) => (buffer[..=i].to_vec(), true),
+                None => (buffer.to_vec(), buffer.is_empty()),
+            };
+            file.consume(chunk.len());
+            Ok((chunk, done))
+        })?;
+        interpreter.reserve(bytes.len().saturating_add(chunk.len()))?;
+        bytes.extend(chunk);
+        if done {
+            break;
+        }
+    }
+    let mut line = String::from_utf8(bytes).map_err(|e| {
+        let path = handle.path.display().to_string();
+        io_error(&path, io::Error::new(io::ErrorKind::InvalidData, e))
+    })?;
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Object::String(line))
+}
+
+fn eof(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("eof", args, 1)?;
+    let handle = expect_handle("eof", &args[0])?;
+    let eof = with_file(handle, |file| Ok(file.fill_buf()?.is_empty()))?;
+    Ok(Object::Bool(eof))
+}
+
+//Closing a file that is already closed does nothing
+fn close(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("close", args, 1)?;
+    let handle = expect_handle("close", &args[0])?;
+    if let Some(mut file) = handle.file.borrow_mut().take() {
+        let path = handle.path.display().to_string();
+        file.get_mut().flush().map_err(|e| io_error(&path, e))?;
+    }
+    Ok(Object::Nothing)
+}
+
+fn isfile(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("isfile", args, 1)?;
+    let (_, checked) = readable(interpreter, "isfile", &args[0])?;
+    Ok(Object::Bool(checked.is_file()))
+}
+
+fn isdir(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("isdir", args, 1)?;
+    let (_, checked) = readable(interpreter, "isdir", &args[0])?;
+    Ok(Object::Bool(checked.is_dir()))
+}
+
+//The names of the entries in a directory, the current one by default, in sorted order
+fn readdir(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("readdir", args, 0, 1)?;
+    let dir = Object::String(".".to_string());
+    let (path, checked) = readable(interpreter, "readdir", args.first().unwrap_or(&dir))?;
+
+    let mut names = vec![];
+    for entry in fs::read_dir(&checked).map_err(|e| io_error(&path, e))? {
+        let entry = entry.map_err(|e| io_error(&path, e))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    Ok(Object::array(
+        names.into_iter().map(Object::String).collect(),
+    ))
+}
+
+fn mkdir(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("mkdir", args, 1)?;
+    let (path, checked) = writable(interpreter, "mkdir", &args[0])?;
+    fs::create_dir(&checked).map_err(|e| io_error(&path, e))?;
+    Ok(Object::String(path))
+}
+
+//Removes a file or an empty directory, a symlink is removed rather than what it points to
+fn rm(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("rm", args, 1)?;
+    let path = expect_string("rm", &args[0])?;
+    let checked = interpreter.check_remove(Path::new(path))?;
+    let metadata = fs::symlink_metadata(&checked).map_err(|e| io_error(path, e))?;
+    let result = match metadata.is_dir() {
+        true => fs::remove_dir(&checked),
+        false => fs::remove_file(&checked),
+    };
+    result.map_err(|e| io_error(path, e))?;
+    Ok(Object::Nothing)
+}
+
+fn joinpath(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    if args.is_empty() {
+        return Err(Error::ArgumentError(
+            "joinpath expects at least one path".to_string(),
+        ));
+    }
+    let mut path = PathBuf::new();
+    for arg in args.iter() {
+        path.push(expect_string("joinpath", arg)?);
+    }
+    Ok(Object::String(path.to_string_lossy().into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::Capabilities;
+    use crate::interpreter::Backend;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use indoc::indoc;
+    use std::env;
+
+    //Runs the script with both backends inside a fresh directory that it can read and
+    //write, which is available to the script as `dir`
+    fn eval_in(name: &str, s: &str) -> Result<Object> {
+        let dir = env::temp_dir().join(format!("lemur_io_test_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let ast = Parser::new(Lexer::new(s)).parse_program().unwrap();
+        let mut results = vec![];
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_capabilities(Capabilities {
+                read: vec![dir.clone()],
+                write: vec![dir.clone()],
+                ..Capabilities::pure()
+            });
+            let path = Object::String(dir.to_string_lossy().into_owned());
+            interpreter.env.insert("dir", path);
+            results.push(interpreter.run(&ast));
+            fs::remove_dir_all(&dir).unwrap();
+            fs::create_dir(&dir).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let result = results.pop().unwrap();
+        assert_eq!(
+            results.pop().unwrap(),
+            result,
+            "The backends disagree on {}",
+            s
+        );
+        result
+    }
+
+    #[test]
+    fn reading_and_writing() {
+        let s = indoc!(
+            r#"
+            path = joinpath(dir, "notes.txt")
+            write(path, "one\ntwo\r\nthree")
+            [read(path), readlines(path), isfile(path), isdir(path), isdir(dir)]
+        "#
+        );
+        let ans = r#"["one\ntwo\r\nthree", ["one", "two", "three"], true, false, true]"#;
+        assert_eq!(eval_in("files", s).unwrap().repr(), ans);
+    }
+
+    #[test]
+    fn file_handles() {
+        let s = indoc!(
+            r#"
+            path = joinpath(dir, "log.txt")
+            file = open(path, "w")
+            write(file, "a\n")
+            write(file, 2)
+            close(file)
+            file = open(path, "a")
+            write(file, "\nb")
+            close(file)
+
+            file = open(path)
+            lines = [readline(file), readline(file), readline(file), eof(file)]
+            close(file)
+            close(file)
+            lines
+        "#
+        );
+        let ans = r#"["a", "2", "b", true]"#;
+        assert_eq!(eval_in("handles", s).unwrap().repr(), ans);
+
+        let s = r#"file = open(joinpath(dir, "x"), "w"); close(file); readline(file)"#;
+        match eval_in("closed", s) {
+            Err(Error::IOError(msg)) => assert!(msg.ends_with("x: the file is closed"), "{}", msg),
+            res => panic!("Expected an IOError but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn long_lines_count_towards_the_memory_limit() {
+        let path = env::temp_dir().join(format!("lemur_io_test_line_{}", std::process::id()));
+        fs::write(&path, "a".repeat(1_000_000)).unwrap();
+
+        let s = format!("readline(open({:?}))", path.display());
+        let ast = Parser::new(Lexer::new(&s)).parse_program().unwrap();
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_capabilities(Capabilities {
+                read: vec![path.clone()],
+                ..Capabilities::pure()
+            });
+            interpreter.set_memory_limit(Some(100_000));
+            match interpreter.run(&ast) {
+                Err(Error::OutOfMemory(_)) => (),
+                res => panic!("Expected to run out of memory but got {:?}", res),
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn directories() {
+        let s = indoc!(
+            r#"
+            mkdir(joinpath(dir, "b"))
+            mkdir(joinpath(dir, "a"))
+            write(joinpath(dir, "c.txt"), "")
+            rm(joinpath(dir, "b"))
+            readdir(dir)
+        "#
+        );
+        assert_eq!(eval_in("dirs", s).unwrap().repr(), r#"["a", "c.txt"]"#);
+
+        match eval_in("missing", r#"read(joinpath(dir, "missing.txt"))"#) {
+            Err(Error::IOError(msg)) => assert!(msg.contains("missing.txt: "), "{}", msg),
+            res => panic!("Expected an IOError but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn removing_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let base = env::temp_dir().join(format!("lemur_io_test_links_{}", std::process::id()));
+        let (dir, outside) = (base.join("sandbox"), base.join("outside"));
+        fs::create_dir_all(outside.join("d")).unwrap();
+        fs::write(outside.join("secret.txt"), "x").unwrap();
+
+        let s = indoc!(
+            r#"
+            rm(joinpath(dir, "file"))
+            rm(joinpath(dir, "dir"))
+            readdir(dir)
+        "#
+        );
+        let ast = Parser::new(Lexer::new(s)).parse_program().unwrap();
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            fs::create_dir_all(&dir).unwrap();
+            symlink(outside.join("secret.txt"), dir.join("file")).unwrap();
+            symlink(outside.join("d"), dir.join("dir")).unwrap();
+
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_capabilities(Capabilities {
+                read: vec![dir.clone()],
+                write: vec![dir.clone()],
+                ..Capabilities::pure()
+            });
+            let path = Object::String(dir.to_string_lossy().into_owned());
+            interpreter.env.insert("dir", path);
+            assert_eq!(interpreter.run(&ast).unwrap().repr(), "[]");
+            assert!(outside.join("secret.txt").is_file());
+            assert!(outside.join("d").is_dir());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn paths_outside_the_sandbox() {
+        let scripts = [
+            r#"read(joinpath(dir, "..", "secret"))"#,
+            r#"write("/tmp/lemur_io_test_escape", "x")"#,
+            r#"readdir("/")"#,
+            r#"open(joinpath(dir, "../x"), "a")"#,
+        ];
+        for s in scripts.iter() {
+            match eval_in("sandbox", s) {
+                Err(Error::PermissionDenied(_)) => (),
+                res => panic!("Expected {} to be denied but got {:?}", s, res),
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use super::{define, expect_args, expect_args_between, expect_float, expect_integer};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+use std::f64::consts;
+
+pub fn load(env: &mut Enviroment) {
+    env.insert("pi", Object::Float(consts::PI));
+    env.insert("e", Object::Float(consts::E));
+    env.insert("Inf", Object::Float(f64::INFINITY));
+    env.insert("NaN", Object::Float(f64::NAN));
+
+    define(env, "sqrt", sqrt);
+    define(env, "cbrt", cbrt);
+    define(env, "exp", exp);
+    define(env, "log", log);
+    define(env, "log2", log2);
+    define(env, "log10", log10);
+    define(env, "sin", sin);
+    define(env, "cos", cos);
+    define(env, "tan", tan);
+    define(env, "asin", asin);
+    define(env, "acos", acos);
+    define(env, "atan", atan);
+    define(env, "sinh", sinh);
+    define(env, "cosh", cosh);
+    define(env, "tanh", tanh);
+    define(env, "asinh", asinh);
+    define(env, "acosh", acosh);
+    define(env, "atanh", atanh);
+    define(env, "abs", abs);
+    define(env, "floor", floor);
+    define(env, "ceil", ceil);
+    define(env, "round", round);
+    define(env, "min", min);
+    define(env, "max", max);
+    define(env, "clamp", clamp);
+    define(env, "hypot", hypot);
+}
+
+//Defines a builtin applying $f to a single number, the domain check is skipped for NaN
+//so it propagates like it does in the underlying float functions
+macro_rules! unary {
+    ($name:ident, $f:expr) => {
+        unary!($name, $f, |_| true);
+    };
+    ($name:ident, $f:expr, $domain:expr) => {
+        fn $name(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+            let name = stringify!($name);
+            expect_args(name, args, 1)?;
+            let x = expect_float(name, &args[0])?;
+            if !x.is_nan() && !$domain(x) {
+                return Err(Error::DomainError(format!(
+                    "{} is not defined for {}",
+                    name, x
+                )));
+            }
+            Ok(Object::Float($f(x)))
+        }
+    };
+}
+
+unary!(sqrt, f64::sqrt, |x| x >= 0.0);
+unary!(cbrt, f64::cbrt);
+unary!(exp, f64::exp);
+unary!(log2, f64::log2, |x| x >= 0.0);
+unary!(log10, f64::log10, |x| x >= 0.0);
+unary!(sin, f64::sin, f64::is_finite);
+unary!(cos, f64::cos, f64::is_finite);
+unary!(tan, f64::tan, f64::is_finite);
+unary!(asin, f64::asin, |x: f64| x.abs() <= 1.0);
+unary!(acos, f64::acos, |x: f64| x.abs() <= 1.0);
+unary!(sinh, f64::sinh);
+unary!(cosh, f64::cosh);
+unary!(tanh, f64::tanh);
+unary!(asinh, f64::asinh);
+unary!(acosh, f64::acosh, |x| x >= 1.0);
+unary!(atanh, f64::atanh, |x: f64| x.abs() <= 1.0);
+unary!(abs, f64::abs);
+
+//log(x) is the natural logarithm, log(b, x) the logarithm of x in base b
+fn log(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("log", args, 1, 2)?;
+    let x = expect_float("log", &args[args.len() - 1])?;
+    if x < 0.0 {
+        return Err(Error::DomainError(format!("log is not defined for {}", x)));
+    }
+    if args.len() == 1 {
+        return Ok(Object::Float(x.ln()));
+    }
+
+    let base = expect_float("log", &args[0])?;
+    if base <= 0.0 || base == 1.0 {
+        return Err(Error::DomainError(format!(
+            "log is not defined for base {}",
+            base
+        )));
+    }
+    Ok(Object::Float(x.log(base)))
+}
+
+//atan(x) or the two argument form atan(y, x)
+fn atan(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("atan", args, 1, 2)?;
+    let y = expect_float("atan", &args[0])?;
+    if args.len() == 1 {
+        return Ok(Object::Float(y.atan()));
+    }
+    let x = expect_float("atan", &args[1])?;
+    Ok(Object::Float(y.atan2(x)))
+}
+
+fn floor(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    with_digits("floor", args, f64::floor)
+}
+
+fn ceil(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    with_digits("ceil", args, f64::ceil)
+}
+
+//Halfway values round to the nearest even number, so round(2.5) == 2
+fn round(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    with_digits("round", args, f64::round_ties_even)
+}
+
+//Applies a rounding function to args[0], optionally keeping args[1] digits after the
+//decimal point. A negative number of digits rounds to tens, hundreds and so on.
+fn with_digits(name: &str, args: &[Object], f: fn(f64) -> f64) -> Result<Object> {
+    expect_args_between(name, args, 1, 2)?;
+    let x = expect_float(name, &args[0])?;
+    if args.len() == 1 {
+        return Ok(Object::Float(f(x)));
+    }
+
+    let digits = expect_integer(name, &args[1])?;
+    let scale = 10f64.powi(digits.clamp(-308, 308) as i32);
+    let rounded = f(x * scale) / scale;
+    //Very large values have no fractional digits to round and would overflow when scaled
+    if rounded.is_finite() || !x.is_finite() {
+        Ok(Object::Float(rounded))
+    } else {
+        Ok(Object::Float(x))
+    }
+}
+
+fn min(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    extremum("min", args, |x, y| y < x)
+}
+
+fn max(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    extremum("max", args, |x, y| y > x)
+}
+
+//Folds the arguments keeping the value preferred by `replace`, NaN wins over everything
+fn extremum(name: &str, args: &[Object], replace: fn(f64, f64) -> bool) -> Result<Object> {
+    if args.is_empty() {
+        return Err(Error::ArgumentError(format!(
+            "{} expects at least one argument",
+            name
+        )));
+    }
+
+    let mut result = expect_float(name, &args[0])?;
+    for arg in &args[1..] {
+        let x = expect_float(name, arg)?;
+        if x.is_nan() || (!result.is_nan() && replace(result, x)) {
+            result = x;
+        }
+    }
+    Ok(Object::Float(result))
+}
+
+fn clamp(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("clamp", args, 3)?;
+    let x = expect_float("clamp", &args[0])?;
+    let lo = expect_float("clamp", &args[1])?;
+    let hi = expect_float("clamp", &args[2])?;
+    //f64::clamp panics on these so they are reported as errors instead
+    if lo > hi || lo.is_nan() || hi.is_nan() {
+        return Err(Error::ArgumentError(format!(
+            "clamp expects lo <= hi but was given {} and {}",
+            lo, hi
+        )));
+    }
+    Ok(Object::Float(x.clamp(lo, hi)))
+}
+
+fn hypot(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("hypot", args, 2)?;
+    let x = expect_float("hypot", &args[0])?;
+    let y = expect_float("hypot", &args[1])?;
+    Ok(Object::Float(x.hypot(y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::eval;
+    use super::*;
+
+    fn float(s: &str) -> f64 {
+        match eval(s) {
+            Ok(Object::Float(x)) => x,
+            res => panic!("Expected {} to evaluate to a float but got {:?}", s, res),
+        }
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(float("pi"), consts::PI);
+        assert_eq!(float("e"), consts::E);
+        assert_eq!(float("-Inf"), f64::NEG_INFINITY);
+        assert!(float("NaN").is_nan());
+    }
+
+    #[test]
+    fn elementary_functions() {
+        assert_eq!(float("sqrt(16)"), 4.0);
+        assert_eq!(float("exp(0)"), 1.0);
+        assert_eq!(float("log(e)"), 1.0);
+        assert_eq!(float("log(2, 8)"), 3.0);
+        assert_eq!(float("log2(1024)"), 10.0);
+        assert_eq!(float("log10(1000)"), 3.0);
+        assert_eq!(float("cbrt(27)"), 3.0);
+        assert_eq!(float("cos(0) + sinh(0) + tanh(0)"), 1.0);
+        assert_eq!(float("atan(1, 1) * 4"), consts::PI);
+        assert_eq!(float("hypot(3, 4)"), 5.0);
+        assert_eq!(float("abs(-2.5)"), 2.5);
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(float("round(2.5)"), 2.0);
+        assert_eq!(float("round(3.5)"), 4.0);
+        assert_eq!(float("round(1.23456, 3)"), 1.235);
+        assert_eq!(float("round(1234, -2)"), 1200.0);
+        assert_eq!(float("floor(-1.5)"), -2.0);
+        assert_eq!(float("ceil(1.21, 1)"), 1.3);
+        assert_eq!(float("round(1e300, 10)"), 1e300);
+    }
+
+    #[test]
+    fn min_max_clamp() {
+        assert_eq!(float("min(3, 1, 2)"), 1.0);
+        assert_eq!(float("max(3, 1, 2)"), 3.0);
+        assert!(float("max(1, NaN)").is_nan());
+        assert_eq!(float("clamp(12, 0, 10)"), 10.0);
+        assert_eq!(float("clamp(-1, 0, 10)"), 0.0);
+    }
+
+    #[test]
+    fn errors() {
+        match eval("sqrt(-1)") {
+            Err(Error::DomainError(_)) => (),
+            res => panic!("Expected a domain error but got {:?}", res),
+        }
+        match eval("acos(2)") {
+            Err(Error::DomainError(_)) => (),
+            res => panic!("Expected a domain error but got {:?}", res),
+        }
+        match eval(r#"sqrt("four")"#) {
+            Err(Error::TypeError(_)) => (),
+            res => panic!("Expected a type error but got {:?}", res),
+        }
+        match eval("round(1.5, 0.5)") {
+            Err(Error::TypeError(_)) => (),
+            res => panic!("Expected a type error but got {:?}", res),
+        }
+        match eval("clamp(1, 10, 0)") {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+        match eval("max()") {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+    }
+}
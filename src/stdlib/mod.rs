@@ -0,0 +1,94 @@
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Result};
+use crate::object::{Builtin, NativeFn, Object};
+
+mod base;
+mod format;
+mod io;
+mod math;
+mod process;
+mod strings;
+mod types;
+
+pub fn load(env: &mut Enviroment) {
+    base::load(env);
+    math::load(env);
+    strings::load(env);
+    format::load(env);
+    io::load(env);
+    process::load(env);
+    types::load(env);
+}
+
+fn define(env: &mut Enviroment, name: &'static str, func: NativeFn) {
+    env.insert(name, Object::Builtin(Builtin { name, func }));
+}
+
+fn expect_args(name: &str, args: &[Object], n: usize) -> Result<()> {
+    if args.len() != n {
+        return Err(Error::ArgumentError(format!(
+            "{} expects {} argument(s) but was given {}",
+            name,
+            n,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+fn expect_args_between(name: &str, args: &[Object], min: usize, max: usize) -> Result<()> {
+    if args.len() < min || args.len() > max {
+        return Err(Error::ArgumentError(format!(
+            "{} expects between {} and {} arguments but was given {}",
+            name,
+            min,
+            max,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+fn expect_float(name: &str, arg: &Object) -> Result<f64> {
+    match arg {
+        Object::Float(x) => Ok(*x),
+        o => Err(Error::TypeError(format!(
+            "{} expects a number but was given {}",
+            name,
+            o.repr()
+        ))),
+    }
+}
+
+fn expect_string<'a>(name: &str, arg: &'a Object) -> Result<&'a str> {
+    match arg {
+        Object::String(s) => Ok(s),
+        o => Err(Error::TypeError(format!(
+            "{} expects a string but was given {}",
+            name,
+            o.repr()
+        ))),
+    }
+}
+
+fn expect_integer(name: &str, arg: &Object) -> Result<i64> {
+    match expect_float(name, arg)? {
+        x if x.fract() == 0.0 && x.abs() <= i64::MAX as f64 => Ok(x as i64),
+        x => Err(Error::TypeError(format!(
+            "{} expects an integer but was given {}",
+            name, x
+        ))),
+    }
+}
+
+#[cfg(test)]
+fn eval(s: &str) -> Result<Object> {
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    let ast = Parser::new(Lexer::new(s))
+        .parse_program()
+        .expect("Failed to parse the test script");
+    crate::vm::eval_both(&mut Interpreter::new(Enviroment::new()), &ast)
+}
@@ -0,0 +1,329 @@
+use super::{define, expect_args, expect_string};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+//How often a running program is checked on while it isn't writing anything
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn load(env: &mut Enviroment) {
+    env.insert("ENV", Object::Env);
+    define(env, "run", run);
+    define(env, "cwd", cwd);
+    define(env, "cd", cd);
+}
+
+//run(["cmd", "arg", ...]) runs a program to completion without a shell and returns
+//[exit code, stdout, stderr], the exit code is nothing if it was killed by a signal. The
+//program is killed if the script times out or is interrupted while it runs, and its
+//output counts towards the memory limit.
+fn run(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("run", args, 1)?;
+    interpreter.capabilities().check_exec()?;
+    let command = match &args[0] {
+        Object::Array(items) => items
+            .borrow()
+            .iter()
+            .map(|item| expect_string("run", item).map(str::to_string))
+            .collect::<Result<Vec<String>>>()?,
+        o => {
+            return Err(Error::TypeError(format!(
+                "run expects an array of strings but was given {}",
+                o.repr()
+            )))
+        }
+    };
+    let (program, args) = match command.split_first() {
+        Some(command) => command,
+        None => return Err(Error::ArgumentError("run expects a command".to_string())),
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .current_dir(interpreter.working_dir()?)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::IOError(format!("{}: {}", program, e)))?;
+    let (sender, receiver) = mpsc::channel();
+    forward(child.stdout.take(), 0, sender.clone());
+    forward(child.stderr.take(), 1, sender);
+
+    let (status, [stdout, stderr]) = match wait(interpreter, &mut child, &receiver) {
+        Ok(result) => result,
+        Err(error) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(match error {
+                Error::IOError(msg) => Error::IOError(format!("{}: {}", program, msg)),
+                error => error,
+            });
+        }
+    };
+    let code = match status.code() {
+        Some(code) => Object::Float(code as f64),
+        None => Object::Nothing,
+    };
+    Ok(Object::array(vec![
+        code,
+        Object::String(String::from_utf8_lossy(&stdout).into_owned()),
+        Object::String(String::from_utf8_lossy(&stderr).into_owned()),
+    ]))
+}
+
+//Sends what's read from a program's stdout or stderr to `run` on another thread, so
+//neither pipe can fill up and block the program while the other is being read
+fn forward(stream: Option<impl Read + Send + 'static>, i: usize, sender: Sender<(usize, Vec<u8>)>) {
+    if let Some(mut stream) = stream {
+        thread::spawn(move || {
+            let mut buffer = [0; 8192];
+            while let Ok(n @ 1..) = stream.read(&mut buffer) {
+                if sender.send((i, buffer[..n].to_vec())).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+//Collects a program's output until it exits, checking the script's limits as it goes
+fn wait(
+    interpreter: &mut Interpreter,
+    child: &mut Child,
+    receiver: &Receiver<(usize, Vec<u8>)>,
+) -> Result<(ExitStatus, [Vec<u8>; 2])> {
+    let mut output = [vec![], vec![]];
+    let mut open = true;
+    loop {
+        interpreter.check_waiting()?;
+        if open {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok((i, chunk)) => {
+                    let len = output[0].len() + output[1].len() + chunk.len();
+                    interpreter.reserve(len)?;
+                    output[i].extend(chunk);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                //Both pipes have been closed
+                Err(RecvTimeoutError::Disconnected) => open = false,
+            }
+        } else {
+            match child.try_wait() {
+                Ok(Some(status)) => return Ok((status, output)),
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(Error::IOError(e.to_string())),
+            }
+        }
+    }
+}
+
+fn cwd(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("cwd", args, 0)?;
+    interpreter.capabilities().check_env()?;
+    let dir = interpreter.working_dir()?;
+    Ok(Object::String(dir.to_string_lossy().into_owned()))
+}
+
+//Changes the directory of the interpreter, the process's and so the host's stays the same
+fn cd(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("cd", args, 1)?;
+    interpreter.capabilities().check_env()?;
+    let path = expect_string("cd", &args[0])?;
+    let dir = interpreter.check_read(Path::new(path))?;
+    match fs::metadata(&dir) {
+        Ok(metadata) if metadata.is_dir() => (),
+        Ok(_) => return Err(Error::IOError(format!("{}: Not a directory", path))),
+        Err(e) => return Err(Error::IOError(format!("{}: {}", path, e))),
+    }
+    interpreter.set_working_dir(dir);
+    Ok(Object::Nothing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::Capabilities;
+    use crate::interpreter::Backend;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use indoc::indoc;
+
+    fn eval_with(capabilities: Capabilities, s: &str) -> Result<Object> {
+        let ast = Parser::new(Lexer::new(s)).parse_program().unwrap();
+        let mut results = vec![];
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_capabilities(capabilities.clone());
+            results.push(interpreter.run(&ast));
+        }
+        let result = results.pop().unwrap();
+        assert_eq!(
+            results.pop().unwrap(),
+            result,
+            "The backends disagree on {}",
+            s
+        );
+        result
+    }
+
+    #[test]
+    fn running_processes() {
+        let capabilities = Capabilities {
+            exec: true,
+            ..Capabilities::pure()
+        };
+        let result = eval_with(
+            capabilities.clone(),
+            r#"run(["sh", "-c", "echo out; echo err >&2; exit 3"])"#,
+        );
+        assert_eq!(result.unwrap().repr(), r#"[3, "out\n", "err\n"]"#);
+
+        match eval_with(capabilities, r#"run(["lemur_missing_program"])"#) {
+            Err(Error::IOError(msg)) => assert!(msg.starts_with("lemur_missing_program: ")),
+            res => panic!("Expected an IOError but got {:?}", res),
+        }
+        match eval_with(Capabilities::pure(), r#"run(["echo"])"#) {
+            Err(Error::PermissionDenied(_)) => (),
+            res => panic!("Expected run to be denied but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn running_processes_stop_at_the_limits() {
+        use std::time::Instant;
+
+        let capabilities = Capabilities {
+            exec: true,
+            ..Capabilities::pure()
+        };
+        let parse = |s| Parser::new(Lexer::new(s)).parse_program().unwrap();
+        let sleep = parse(r#"run(["sleep", "5"])"#);
+        let noisy = parse(r#"run(["sh", "-c", "head -c 1000000 /dev/zero"])"#);
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_capabilities(capabilities.clone());
+
+            let start = Instant::now();
+            interpreter.set_timeout(Some(Duration::from_millis(50)));
+            match interpreter.run(&sleep) {
+                Err(Error::Timeout(_)) => (),
+                res => panic!("Expected a timeout but got {:?}", res),
+            }
+            interpreter.set_timeout(None);
+
+            let handle = interpreter.interrupt_handle();
+            let thread = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                handle.interrupt();
+            });
+            match interpreter.run(&sleep) {
+                Err(Error::Interrupted(_)) => (),
+                res => panic!("Expected an interrupt but got {:?}", res),
+            }
+            thread.join().unwrap();
+            assert!(start.elapsed() < Duration::from_secs(4));
+
+            interpreter.set_memory_limit(Some(10_000));
+            match interpreter.run(&noisy) {
+                Err(Error::OutOfMemory(_)) => (),
+                res => panic!("Expected to run out of memory but got {:?}", res),
+            }
+            interpreter.set_memory_limit(None);
+
+            //An interrupt sent while nothing was running doesn't stop the next script
+            interpreter.interrupt_handle().interrupt();
+            let echo = parse(r#"run(["echo", "hi"])"#);
+            assert_eq!(interpreter.run(&echo).unwrap().repr(), r#"[0, "hi\n", ""]"#);
+        }
+    }
+
+    #[test]
+    fn changing_directory_only_affects_the_interpreter() {
+        use std::env;
+
+        let dir = env::temp_dir().join(format!("lemur_cd_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/file.txt"), "").unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let before = env::current_dir().unwrap();
+
+        let s = format!(
+            "cd({:?})\ncd(\"sub\")\n[cwd(), isfile(\"file.txt\"), run([\"pwd\"])[2]]",
+            dir.display()
+        );
+        let sub = dir.join("sub").display().to_string();
+        let result = eval_with(Capabilities::all(), &s);
+        assert_eq!(env::current_dir().unwrap(), before);
+        assert_eq!(
+            result.unwrap().repr(),
+            format!("[{:?}, true, {:?}]", sub, format!("{}\n", sub))
+        );
+
+        let s = format!("cd({:?})", dir.join("sub/file.txt").display());
+        match eval_with(Capabilities::all(), &s) {
+            Err(Error::IOError(msg)) => assert!(msg.ends_with(": Not a directory")),
+            res => panic!("Expected an IOError but got {:?}", res),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn environment_variables() {
+        let capabilities = Capabilities {
+            env: true,
+            read: vec!["/".into()],
+            ..Capabilities::pure()
+        };
+        let s = indoc!(
+            r#"
+            ENV["LEMUR_TEST_VAR"] = 1
+            value = ENV["LEMUR_TEST_VAR"]
+            ENV["LEMUR_TEST_VAR"] = nothing
+            dir = cwd()
+            cd(dir)
+            [value, cwd() == dir]
+        "#
+        );
+        assert_eq!(
+            eval_with(capabilities.clone(), s).unwrap().repr(),
+            r#"["1", true]"#
+        );
+
+        match eval_with(capabilities.clone(), r#"ENV["LEMUR_TEST_VAR"]"#) {
+            Err(Error::KeyError(msg)) => {
+                assert_eq!(msg, "the environment variable LEMUR_TEST_VAR is not set")
+            }
+            res => panic!("Expected a KeyError but got {:?}", res),
+        }
+        for s in [
+            r#"ENV[""] = "x""#,
+            r#"ENV["A=B"] = "x""#,
+            r#"ENV[""] = nothing"#,
+            r#"ENV["A\0B"] = "x""#,
+            r#"ENV["LEMUR_TEST_VAR"] = "a\0b""#,
+        ]
+        .iter()
+        {
+            match eval_with(capabilities.clone(), s) {
+                Err(Error::ArgumentError(_)) => (),
+                res => panic!("Expected {} to be an ArgumentError but got {:?}", s, res),
+            }
+        }
+        for s in [r#"ENV["HOME"]"#, r#"ENV["X"] = "1""#, "cwd()", r#"cd("/")"#].iter() {
+            match eval_with(Capabilities::pure(), s) {
+                Err(Error::PermissionDenied(_)) => (),
+                res => panic!("Expected {} to be denied but got {:?}", s, res),
+            }
+        }
+    }
+}
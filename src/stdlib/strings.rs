@@ -0,0 +1,282 @@
+use super::{define, expect_args, expect_args_between, expect_integer, expect_string};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+use crate::types::Type;
+
+pub fn load(env: &mut Enviroment) {
+    define(env, "length", length);
+    define(env, "uppercase", uppercase);
+    define(env, "lowercase", lowercase);
+    define(env, "split", split);
+    define(env, "join", join);
+    define(env, "strip", strip);
+    define(env, "lstrip", lstrip);
+    define(env, "rstrip", rstrip);
+    define(env, "replace", replace);
+    define(env, "startswith", startswith);
+    define(env, "endswith", endswith);
+    define(env, "occursin", occursin);
+    define(env, "repeat", repeat);
+    define(env, "chars", chars);
+    define(env, "parse", parse);
+}
+
+//Defines a builtin mapping a single string to a new string
+macro_rules! map_string {
+    ($name:ident, $f:expr) => {
+        fn $name(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+            let name = stringify!($name);
+            expect_args(name, args, 1)?;
+            let s = expect_string(name, &args[0])?;
+            Ok(Object::String($f(s).into()))
+        }
+    };
+}
+
+map_string!(uppercase, str::to_uppercase);
+map_string!(lowercase, str::to_lowercase);
+map_string!(strip, str::trim);
+map_string!(lstrip, str::trim_start);
+map_string!(rstrip, str::trim_end);
+
+//Defines a builtin testing a string against a second string argument
+macro_rules! test_string {
+    ($name:ident, $f:expr) => {
+        fn $name(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+            let name = stringify!($name);
+            expect_args(name, args, 2)?;
+            let s = expect_string(name, &args[0])?;
+            let pattern = expect_string(name, &args[1])?;
+            Ok(Object::Bool($f(s, pattern)))
+        }
+    };
+}
+
+test_string!(startswith, str::starts_with::<&str>);
+test_string!(endswith, str::ends_with::<&str>);
+test_string!(occursin, is_substring);
+
+//Follows the argument order of occursin(needle, haystack)
+fn is_substring(needle: &str, haystack: &str) -> bool {
+    haystack.contains(needle)
+}
+
+//The number of characters in a string or elements in an array
+fn length(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("length", args, 1)?;
+    let n = match &args[0] {
+        Object::String(s) => s.chars().count(),
+        Object::Array(items) => items.borrow().len(),
+        o => {
+            return Err(Error::TypeError(format!(
+                "length is not defined for {}",
+                o.repr()
+            )))
+        }
+    };
+    Ok(Object::Float(n as f64))
+}
+
+//split(s) splits on runs of whitespace, split(s, delim) on every delimiter
+fn split(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("split", args, 1, 2)?;
+    let s = expect_string("split", &args[0])?;
+    let parts: Vec<&str> = if args.len() == 1 {
+        s.split_whitespace().collect()
+    } else {
+        match expect_string("split", &args[1])? {
+            "" => return Err(Error::ArgumentError("split delimiter is empty".to_string())),
+            delim => s.split(delim).collect(),
+        }
+    };
+
+    let parts = parts
+        .into_iter()
+        .map(|part| Object::String(part.to_string()))
+        .collect();
+    Ok(Object::array(parts))
+}
+
+//Joins the printed form of each element, so join([1, "a"], "-") is "1-a"
+fn join(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args_between("join", args, 1, 2)?;
+    let delim = match args.get(1) {
+        Some(delim) => expect_string("join", delim)?,
+        None => "",
+    };
+    let items = match &args[0] {
+        Object::Array(items) => items.borrow(),
+        o => {
+            return Err(Error::TypeError(format!(
+                "join expects an array but was given {}",
+                o.repr()
+            )))
+        }
+    };
+
+    let mut s = String::new();
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            interpreter.reserve(s.len().saturating_add(delim.len()))?;
+            s.push_str(delim);
+        }
+        interpreter.print_to(&mut s, item, false)?;
+    }
+    Ok(Object::String(s))
+}
+
+//replace(s, old, new) replaces every occurrence of old
+fn replace(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("replace", args, 3)?;
+    let s = expect_string("replace", &args[0])?;
+    let old = expect_string("replace", &args[1])?;
+    let new = expect_string("replace", &args[2])?;
+    if old.is_empty() {
+        return Err(Error::ArgumentError("replace pattern is empty".to_string()));
+    }
+    //Like repeat the result can be far larger than the arguments
+    let matches = s.matches(old).count();
+    let size = (s.len() - matches * old.len()).saturating_add(matches.saturating_mul(new.len()));
+    interpreter.reserve(size)?;
+    Ok(Object::String(s.replace(old, new)))
+}
+
+fn repeat(interpreter: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("repeat", args, 2)?;
+    let s = expect_string("repeat", &args[0])?;
+    match expect_integer("repeat", &args[1])? {
+        n if n >= 0 => {
+            //The result can be far larger than the arguments so it's checked up front
+            interpreter.reserve(s.len().saturating_mul(n as usize))?;
+            Ok(Object::String(s.repeat(n as usize)))
+        }
+        n => Err(Error::ArgumentError(format!(
+            "repeat count must be non negative but was given {}",
+            n
+        ))),
+    }
+}
+
+//An array of the characters in a string, each as a string of length 1
+fn chars(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("chars", args, 1)?;
+    let s = expect_string("chars", &args[0])?;
+    let chars = s.chars().map(|c| Object::String(c.to_string())).collect();
+    Ok(Object::array(chars))
+}
+
+//parse(Float, s) reads a number from a string, surrounding whitespace is ignored
+fn parse(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("parse", args, 2)?;
+    match &args[0] {
+        Object::Type(Type::Float) => (),
+        o => {
+            return Err(Error::ArgumentError(format!(
+                "Cannot parse a value of type {}",
+                o.repr()
+            )))
+        }
+    }
+
+    let s = expect_string("parse", &args[1])?;
+    match s.trim().parse::<f64>() {
+        Ok(x) => Ok(Object::Float(x)),
+        Err(_) => Err(Error::ArgumentError(format!(
+            "Cannot parse {} as Float",
+            args[1].repr()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::eval;
+    use super::*;
+
+    fn string(s: &str) -> String {
+        match eval(s) {
+            Ok(Object::String(value)) => value,
+            res => panic!("Expected {} to evaluate to a string but got {:?}", s, res),
+        }
+    }
+
+    #[test]
+    fn case_and_whitespace() {
+        assert_eq!(string(r#"uppercase("straße")"#), "STRASSE");
+        assert_eq!(string(r#"lowercase("ÀB")"#), "àb");
+        assert_eq!(string(r#"strip("  a b ")"#), "a b");
+        assert_eq!(string(r#"lstrip("  a ")"#), "a ");
+        assert_eq!(string(r#"rstrip("  a ")"#), "  a");
+    }
+
+    #[test]
+    fn split_and_join() {
+        let res = eval(r#"split(" a  b c ")"#).unwrap();
+        assert_eq!(res.repr(), r#"["a", "b", "c"]"#);
+        let res = eval(r#"split("a,,b", ",")"#).unwrap();
+        assert_eq!(res.repr(), r#"["a", "", "b"]"#);
+        assert_eq!(string(r#"join(["x", 1, true], ", ")"#), "x, 1, true");
+        assert_eq!(string(r#"join(split("a b"))"#), "ab");
+    }
+
+    #[test]
+    fn searching_and_replacing() {
+        assert_eq!(eval(r#"startswith("lemur", "le")"#), Ok(Object::Bool(true)));
+        assert_eq!(eval(r#"endswith("lemur", "le")"#), Ok(Object::Bool(false)));
+        assert_eq!(eval(r#"occursin("mu", "lemur")"#), Ok(Object::Bool(true)));
+        assert_eq!(string(r#"replace("a-b-c", "-", "+")"#), "a+b+c");
+        assert_eq!(string(r#"repeat("ab", 3)"#), "ababab");
+    }
+
+    #[test]
+    fn replacing_counts_towards_the_memory_limit() {
+        use crate::enviroment::Enviroment;
+        use crate::interpreter::Backend;
+        use crate::lexer::Lexer;
+        use crate::parser::Parser;
+
+        let s = r#"replace(repeat("a", 200000), "a", repeat("b", 50000))"#;
+        let ast = Parser::new(Lexer::new(s)).parse_program().unwrap();
+        for backend in [Backend::TreeWalker, Backend::Bytecode].iter() {
+            let mut interpreter = Interpreter::new(Enviroment::new());
+            interpreter.set_backend(*backend);
+            interpreter.set_memory_limit(Some(1_000_000));
+            match interpreter.run(&ast) {
+                Err(Error::OutOfMemory(_)) => (),
+                res => panic!("Expected to run out of memory but got {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn unicode_characters() {
+        assert_eq!(eval(r#"length("αβγ")"#), Ok(Object::Float(3.0)));
+        assert_eq!(eval(r#"length(["a", "b"])"#), Ok(Object::Float(2.0)));
+        assert_eq!(string(r#"chars("añb")[2]"#), "ñ");
+        assert_eq!(string(r#""日本語"[3]"#), "語");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(eval(r#"parse(Float, " 2.5e2 ")"#), Ok(Object::Float(250.0)));
+        assert_eq!(eval("Float(true)"), Ok(Object::Float(1.0)));
+        assert_eq!(string("string(1.5)"), "1.5");
+        match eval(r#"parse(Float, "abc")"#) {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn argument_errors() {
+        match eval(r#"repeat("a", -1)"#) {
+            Err(Error::ArgumentError(_)) => (),
+            res => panic!("Expected an argument error but got {:?}", res),
+        }
+        match eval("uppercase(1)") {
+            Err(Error::TypeError(_)) => (),
+            res => panic!("Expected a type error but got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use super::{define, expect_args};
+use crate::enviroment::Enviroment;
+use crate::interpreter::{Error, Interpreter, Result};
+use crate::object::Object;
+use crate::symbol::Symbol;
+use crate::types::Type;
+
+pub fn load(env: &mut Enviroment) {
+    for ty in Type::BUILTIN.iter() {
+        env.insert(&ty.name(), Object::Type(ty.clone()));
+    }
+    define(env, "typeof", type_of);
+    define(env, "isa", isa);
+    define(env, "supertype", supertype);
+    define(env, "fieldnames", fieldnames);
+    define(env, "convert", convert);
+}
+
+fn expect_type<'a>(name: &str, arg: &'a Object) -> Result<&'a Type> {
+    match arg {
+        Object::Type(ty) => Ok(ty),
+        o => Err(Error::TypeError(format!(
+            "{} expects a type but was given {}",
+            name,
+            o.repr()
+        ))),
+    }
+}
+
+fn type_of(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("typeof", args, 1)?;
+    Ok(Object::Type(Type::of(&args[0])))
+}
+
+//isa(x, T) is true when the type of x is T or below it, such as isa(1, Number)
+fn isa(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("isa", args, 2)?;
+    let ty = expect_type("isa", &args[1])?;
+    Ok(Object::Bool(ty.isa(&args[0])))
+}
+
+//The supertype of Any is nothing
+fn supertype(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("supertype", args, 1)?;
+    let ty = expect_type("supertype", &args[0])?;
+    Ok(ty.supertype().map_or(Object::Nothing, Object::Type))
+}
+
+//The names of the fields of a struct as symbols, caught errors have fields too
+fn fieldnames(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("fieldnames", args, 1)?;
+    let names = match expect_type("fieldnames", &args[0])? {
+        Type::Struct(ty) => ty.fields.iter().map(|f| Object::Symbol(*f)).collect(),
+        Type::Error => ["kind", "message", "stacktrace"]
+            .iter()
+            .map(|f| Object::Symbol(Symbol::intern(f)))
+            .collect(),
+        _ => vec![],
+    };
+    Ok(Object::array(names))
+}
+
+//convert(T, x) returns x if it's already a T, otherwise it's constructed as T(x)
+fn convert(_: &mut Interpreter, args: &[Object]) -> Result<Object> {
+    expect_args("convert", args, 2)?;
+    let ty = expect_type("convert", &args[0])?;
+    match ty {
+        ty if ty.isa(&args[1]) => Ok(args[1].clone()),
+        Type::Struct(_) => Err(Error::TypeError(format!(
+            "Cannot convert {} to {}",
+            args[1].repr(),
+            ty
+        ))),
+        ty => ty.construct(&args[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::eval;
+
+    #[test]
+    fn types_of_values() {
+        let res = eval(r#"[typeof(1), typeof("a"), typeof(typeof), typeof(Float), typeof([])]"#);
+        assert_eq!(
+            res.unwrap().repr(),
+            "[Float, String, Function, Type, Array]"
+        );
+        let res =
+            eval("struct P x end; [typeof(P(1)), typeof(P), fieldnames(P), fieldnames(Float)]");
+        assert_eq!(res.unwrap().repr(), "[P, Type, [:x], []]");
+        let res = eval("[supertype(Float), supertype(Real), supertype(Any)]");
+        assert_eq!(res.unwrap().repr(), "[Real, Number, nothing]");
+    }
+
+    #[test]
+    fn isa_checks() {
+        let s = r#"[isa(1, Float), isa(1, Number), isa(1, Any), isa("a", AbstractString),
+                    isa(true, Number), isa(nothing, Nothing)]"#;
+        assert_eq!(
+            eval(s).unwrap().repr(),
+            "[true, true, true, true, false, true]"
+        );
+        assert!(eval("isa(1, 2)").is_err());
+    }
+
+    #[test]
+    fn abstract_types_in_methods() {
+        let s = r#"
+            function kind(x::Any) "any" end
+            function kind(x::Number) "number" end
+            function kind(x::Real) "real" end
+            function kind(s::AbstractString) "string" end
+            [kind(1), kind("a"), kind(true)]
+        "#;
+        assert_eq!(eval(s).unwrap().repr(), r#"["real", "string", "any"]"#);
+    }
+
+    #[test]
+    fn conversions() {
+        let res = eval(
+            r#"[convert(Float, 3), convert(Float, true), convert(Number, 2), convert(Any, "a")]"#,
+        );
+        assert_eq!(res.unwrap().repr(), r#"[3, 1, 2, "a"]"#);
+        for s in [
+            r#"convert(Float, "1")"#,
+            "convert(Number, true)",
+            "struct P x end; convert(P, 1)",
+        ]
+        .iter()
+        {
+            match eval(s) {
+                Err(error) => assert_eq!(error.kind(), "TypeError", "{}", s),
+                res => panic!("Expected {} to fail but got {:?}", s, res),
+            }
+        }
+    }
+}
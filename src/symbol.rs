@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+//An interned identifier, comparing and hashing a symbol is comparing an integer
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+//The most bytes of names from scripts each thread stores. Symbols are never freed, so
+//without a limit a host parsing untrusted scripts on a long lived thread would grow
+//without bound.
+pub const MAX_NAME_BYTES: usize = 16 * 1024 * 1024;
+
+//Each name is stored once in a table owned by the thread, and freed when it exits
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+    bytes: usize,
+}
+
+impl Interner {
+    fn insert(&mut self, name: &str) -> Symbol {
+        let name: Rc<str> = Rc::from(name);
+        let symbol = Symbol(self.names.len() as u32);
+        self.bytes += name.len();
+        self.names.push(name.clone());
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    //Interns a name the interpreter itself uses, which always succeeds
+    pub fn intern(name: &str) -> Self {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            match interner.ids.get(name) {
+                Some(&symbol) => symbol,
+                None => interner.insert(name),
+            }
+        })
+    }
+
+    //Interns a name from a script, None once the thread has stored MAX_NAME_BYTES
+    pub fn try_intern(name: &str) -> Option<Self> {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            match interner.ids.get(name) {
+                Some(&symbol) => Some(symbol),
+                None if interner.bytes + name.len() > MAX_NAME_BYTES => None,
+                None => Some(interner.insert(name)),
+            }
+        })
+    }
+
+    //Finds the symbol for a name without interning it, used for lookups by name so
+    //looking up a missing variable doesn't grow the interner
+    pub fn lookup(name: &str) -> Option<Self> {
+        INTERNER.with(|interner| interner.borrow().ids.get(name).copied())
+    }
+
+    pub fn as_str(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({})", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let a = Symbol::intern("alpha");
+        assert_eq!(a, Symbol::intern(&String::from("alpha")));
+        assert_ne!(a, Symbol::intern("beta"));
+        assert_eq!(&*a.as_str(), "alpha");
+        assert_eq!(Symbol::lookup("alpha"), Some(a));
+        assert_eq!(Symbol::lookup("never interned"), None);
+    }
+
+    #[test]
+    fn names_from_scripts_are_bounded() {
+        use crate::lexer::Lexer;
+        use crate::parser::{Error, Parser};
+
+        //Each test runs on its own thread, so this only fills this test's table
+        let known = Symbol::intern("known");
+        let mut i = 0;
+        while Symbol::try_intern(&format!("{:0>1000}", i)).is_some() {
+            i += 1;
+        }
+        assert!(i > MAX_NAME_BYTES / 1000 - 100);
+        assert_eq!(Symbol::try_intern("known"), Some(known));
+
+        let unseen = format!("{} = known", "z".repeat(1000));
+        match Parser::new(Lexer::new(&unseen)).parse_program() {
+            Err(Error::InvalidSyntax(msg)) => assert!(msg.starts_with("Too many distinct names")),
+            res => panic!("Expected the name table to be full but got {:?}", res),
+        }
+        assert!(Parser::new(Lexer::new("known = 1")).parse_program().is_ok());
+    }
+}
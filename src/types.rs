@@ -0,0 +1,210 @@
+use crate::atom::Struct;
+use crate::interpreter::{Error, Result};
+use crate::object::{Instance, Object};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+//The type of a value, used in method signatures and isa checks. Every value is of exactly
+//one concrete type, the abstract types Any, Number, Real and AbstractString only group
+//other types.
+//
+//  Any
+//  ├─ Number ─ Real ─ Float
+//  ├─ AbstractString ─ String
+//  └─ Bool, Symbol, Array, Function, File, Error, Env, Type, Nothing and structs
+#[derive(Debug, Clone)]
+pub enum Type {
+    Any,
+    Number,
+    Real,
+    AbstractString,
+    Float,
+    String,
+    Bool,
+    Symbol,
+    Array,
+    //Builtins, functions and generic functions
+    Function,
+    File,
+    Error,
+    Env,
+    Type,
+    Nothing,
+    Struct(Rc<Struct>),
+}
+
+//Struct types are only equal to themselves, even if another has the same name and fields,
+//and never to a builtin type that shares their name
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Type::Struct(a), Type::Struct(b)) => Rc::ptr_eq(a, b),
+            (Type::Struct(_), _) | (_, Type::Struct(_)) => false,
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl Type {
+    //The builtin types, each is defined as a global of the same name
+    pub const BUILTIN: [Type; 15] = [
+        Type::Any,
+        Type::Number,
+        Type::Real,
+        Type::AbstractString,
+        Type::Float,
+        Type::String,
+        Type::Bool,
+        Type::Symbol,
+        Type::Array,
+        Type::Function,
+        Type::File,
+        Type::Error,
+        Type::Env,
+        Type::Type,
+        Type::Nothing,
+    ];
+
+    pub fn of(object: &Object) -> Type {
+        match object {
+            Object::String(_) => Type::String,
+            Object::Symbol(_) => Type::Symbol,
+            Object::Float(_) => Type::Float,
+            Object::Bool(_) => Type::Bool,
+            Object::Array(_) => Type::Array,
+            Object::Builtin(_) | Object::Function(_) | Object::Generic(_) => Type::Function,
+            Object::File(_) => Type::File,
+            Object::Error(_) => Type::Error,
+            Object::Env => Type::Env,
+            Object::Type(_) => Type::Type,
+            Object::Struct(instance) => Type::Struct(instance.ty.clone()),
+            Object::Nothing => Type::Nothing,
+        }
+    }
+
+    pub fn name(&self) -> Cow<'static, str> {
+        let name = match self {
+            Type::Any => "Any",
+            Type::Number => "Number",
+            Type::Real => "Real",
+            Type::AbstractString => "AbstractString",
+            Type::Float => "Float",
+            Type::String => "String",
+            Type::Bool => "Bool",
+            Type::Symbol => "Symbol",
+            Type::Array => "Array",
+            Type::Function => "Function",
+            Type::File => "File",
+            Type::Error => "Error",
+            Type::Env => "Env",
+            Type::Type => "Type",
+            Type::Nothing => "Nothing",
+            Type::Struct(ty) => return Cow::Owned(ty.name.to_string()),
+        };
+        Cow::Borrowed(name)
+    }
+
+    //The type directly above this one, Any is the only type without one
+    pub fn supertype(&self) -> Option<Type> {
+        match self {
+            Type::Any => None,
+            Type::Float => Some(Type::Real),
+            Type::Real => Some(Type::Number),
+            Type::String => Some(Type::AbstractString),
+            _ => Some(Type::Any),
+        }
+    }
+
+    //Abstract types only group other types, no value is of one of them
+    pub fn is_abstract(&self) -> bool {
+        matches!(
+            self,
+            Type::Any | Type::Number | Type::Real | Type::AbstractString
+        )
+    }
+
+    pub fn is_subtype(&self, other: &Type) -> bool {
+        self == other || self.supertype().is_some_and(|ty| ty.is_subtype(other))
+    }
+
+    pub fn isa(&self, object: &Object) -> bool {
+        Type::of(object).is_subtype(self)
+    }
+
+    //Calling a struct makes an instance with the arguments as its fields, calling Float
+    //converts a number or boolean. Abstract types can't be constructed.
+    pub fn construct(&self, args: &[Object]) -> Result<Object> {
+        let expected = match self {
+            Type::Struct(ty) => ty.fields.len(),
+            _ => 1,
+        };
+        if args.len() != expected {
+            return Err(Error::ArgumentError(format!(
+                "{} expects {} argument(s) but was given {}",
+                self,
+                expected,
+                args.len()
+            )));
+        }
+
+        match (self, args) {
+            (Type::Struct(ty), _) => Ok(Object::Struct(Rc::new(Instance {
+                ty: ty.clone(),
+                fields: RefCell::new(args.to_vec()),
+            }))),
+            (Type::Float, [Object::Float(x)]) => Ok(Object::Float(*x)),
+            (Type::Float, [Object::Bool(b)]) => Ok(Object::Float(if *b { 1.0 } else { 0.0 })),
+            (_, [o]) => Err(Error::TypeError(format!(
+                "Cannot convert {} to {}",
+                o.repr(),
+                self
+            ))),
+            _ => unreachable!("The arguments were checked above"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn hierarchy() {
+        assert!(Type::Float.is_subtype(&Type::Real));
+        assert!(Type::Float.is_subtype(&Type::Number));
+        assert!(Type::Float.is_subtype(&Type::Any));
+        assert!(Type::String.is_subtype(&Type::AbstractString));
+        assert!(!Type::Number.is_subtype(&Type::Float));
+        assert!(!Type::Bool.is_subtype(&Type::Number));
+        assert!(!Type::String.is_subtype(&Type::Number));
+        assert_eq!(Type::Any.supertype(), None);
+
+        assert!(Type::Number.isa(&Object::Float(1.0)));
+        assert!(!Type::Real.isa(&Object::Nothing));
+        assert_eq!(Type::of(&Object::array(vec![])), Type::Array);
+    }
+
+    #[test]
+    fn structs_never_equal_builtins() {
+        let number = Type::Struct(Rc::new(Struct {
+            name: Symbol::intern("Number"),
+            fields: vec![],
+            mutable: false,
+        }));
+        assert_ne!(number, Type::Number);
+        assert_ne!(Type::Number, number);
+        assert_eq!(number, number.clone());
+        assert!(!number.is_subtype(&Type::Number));
+        assert!(!Type::Float.is_subtype(&number));
+    }
+}
@@ -0,0 +1,451 @@
+use crate::atom::{self, Atom, Function, AST};
+use crate::compiler::{compile, compile_function, Chunk, Op};
+use crate::enviroment::Frame;
+use crate::interpreter::{self, Error, Interpreter, Result};
+use crate::object::{Closure, ErrorValue, Object};
+use std::rc::Rc;
+
+//A function being run by the vm. Locals live in the same heap frames the tree walker
+//uses so closures can be shared between the two backends.
+struct CallFrame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    frame: Option<Rc<Frame>>,
+    //The height of the value stack when the call started, a return leaves only its value
+    //above it
+    base: usize,
+}
+
+//A try block being run, where to unwind to when an error is raised. Returning from a
+//function also unwinds to its finally blocks.
+struct Handler {
+    calls: usize,
+    stack: usize,
+    ip: usize,
+    finally: bool,
+}
+
+struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    stack: Vec<Object>,
+    calls: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    //The values of globals that catch blocks are using, see Op::BindGlobal
+    bindings: Vec<Option<Object>>,
+}
+
+//Compiles and runs a top level script
+pub fn run(interpreter: &mut Interpreter, ast: &AST) -> Result<Object> {
+    let chunk = Rc::new(compile(ast));
+    let depth = interpreter.backtrace.len();
+    let result = Vm::new(interpreter).execute(chunk, None);
+    interpreter.backtrace.truncate(depth);
+    result
+}
+
+//Runs a closure called from outside the vm, such as from a builtin or an overloaded
+//operator. Each call nests another execute on the native stack, so it's grown as needed.
+pub fn call(interpreter: &mut Interpreter, closure: &Closure, args: &[Object]) -> Result<Object> {
+    let depth = interpreter.backtrace.len();
+    interpreter.enter(closure.function.name)?;
+    let result = match interpreter::new_frame(closure, args) {
+        Ok(frame) => {
+            let chunk = compiled(interpreter, &closure.function);
+            atom::grow_stack(|| Vm::new(interpreter).execute(chunk, Some(frame)))
+        }
+        Err(error) => Err(error),
+    };
+    interpreter.backtrace.truncate(depth);
+    result
+}
+
+//The value a function definition assigns, see interpreter::define_method
+fn define(existing: Option<Object>, value: Object) -> Object {
+    match value {
+        Object::Function(method) => interpreter::define_method(existing, method),
+        value => value,
+    }
+}
+
+//Function bodies are compiled the first time they are called
+fn compiled(interpreter: &mut Interpreter, function: &Rc<Function>) -> Rc<Chunk> {
+    let (_, chunk) = interpreter
+        .compiled
+        .entry(Rc::as_ptr(function))
+        .or_insert_with(|| (function.clone(), Rc::new(compile_function(function))));
+    chunk.clone()
+}
+
+impl<'a> Vm<'a> {
+    fn new(interpreter: &'a mut Interpreter) -> Self {
+        Vm {
+            interpreter,
+            stack: vec![],
+            calls: vec![],
+            handlers: vec![],
+            bindings: vec![],
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack
+            .pop()
+            .expect("The compiler keeps the stack balanced")
+    }
+
+    fn pop_n(&mut self, n: u32) -> Vec<Object> {
+        self.stack.split_off(self.stack.len() - n as usize)
+    }
+
+    fn call_frame(&mut self) -> &mut CallFrame {
+        self.calls
+            .last_mut()
+            .expect("There is always a function being run")
+    }
+
+    fn frame(&mut self) -> &Frame {
+        self.call_frame()
+            .frame
+            .as_ref()
+            .expect("Local variables are only used inside a function call")
+    }
+
+    fn execute(&mut self, chunk: Rc<Chunk>, frame: Option<Rc<Frame>>) -> Result<Object> {
+        let base = self.stack.len();
+        self.calls.push(CallFrame {
+            chunk,
+            ip: 0,
+            frame,
+            base,
+        });
+
+        loop {
+            let finished = match self.dispatch() {
+                Ok(finished) => finished,
+                Err(error) => self.handle(error)?,
+            };
+            if let Some(value) = finished {
+                return Ok(value);
+            }
+        }
+    }
+
+    //Returns from the function being run, giving back the value if it was the first call
+    fn ret(&mut self, value: Object) -> Option<Object> {
+        let call = self
+            .calls
+            .pop()
+            .expect("There is always a function being run");
+        if self.calls.is_empty() {
+            return Some(value);
+        }
+        self.stack.truncate(call.base);
+        self.stack.push(value);
+        self.interpreter.backtrace.pop();
+        None
+    }
+
+    //Unwinds to the innermost try block, or gives the error back if there isn't one
+    fn handle(&mut self, error: Error) -> Result<Option<Object>> {
+        //A return runs the finally blocks it's inside of before leaving the function
+        if let Error::Return(value) = error {
+            while let Some(handler) = self.handlers.last() {
+                if handler.calls != self.calls.len() {
+                    break;
+                }
+                let handler = self.handlers.pop().expect("The handler was just seen");
+                if handler.finally {
+                    self.stack.truncate(handler.stack);
+                    let error = Error::Return(value);
+                    let trace = vec![];
+                    self.stack
+                        .push(Object::Error(Rc::new(ErrorValue { error, trace })));
+                    self.call_frame().ip = handler.ip;
+                    return Ok(None);
+                }
+            }
+            return Ok(self.ret(value));
+        }
+
+        self.interpreter.record_trace(&error);
+        let handler = match self.handlers.pop() {
+            Some(handler) if error.is_catchable() => handler,
+            _ => return Err(error),
+        };
+        //Every call above the bottom one entered the backtrace
+        while self.calls.len() > handler.calls {
+            self.calls.pop();
+            self.interpreter.backtrace.pop();
+        }
+        self.stack.truncate(handler.stack);
+        let caught = self.interpreter.catch(error);
+        self.stack.push(caught);
+        self.call_frame().ip = handler.ip;
+        Ok(None)
+    }
+
+    //Runs one instruction, returning the script's value once it has finished
+    fn dispatch(&mut self) -> Result<Option<Object>> {
+        self.interpreter.step()?;
+        let call = self.call_frame();
+        let op = call.chunk.code[call.ip];
+        call.ip += 1;
+
+        match op {
+            Op::Constant(idx) => {
+                let constant = self.call_frame().chunk.constants[idx as usize].clone();
+                self.stack.push(constant);
+            }
+            Op::Nothing => self.stack.push(Object::Nothing),
+            Op::True => self.stack.push(Object::Bool(true)),
+            Op::False => self.stack.push(Object::Bool(false)),
+            Op::GetGlobal(key) => match self.interpreter.env.get_symbol(key) {
+                Some(object) => self.stack.push(object.clone()),
+                None => return Err(Error::UndefinedVariable(key.to_string())),
+            },
+            Op::SetGlobal(key) => {
+                let value = self.pop();
+                self.interpreter.env.insert_symbol(key, value);
+                self.stack.push(Object::Symbol(key));
+            }
+            Op::BindGlobal(key) => {
+                let value = self.pop();
+                let previous = self.interpreter.env.replace_symbol(key, Some(value));
+                self.bindings.push(previous);
+            }
+            Op::UnbindGlobal(key) => {
+                let previous = self.bindings.pop().expect("Bound by BindGlobal");
+                self.interpreter.env.replace_symbol(key, previous);
+            }
+            Op::GetLocal(local) => match self.frame().get(local.depth, local.slot) {
+                Some(object) => self.stack.push(object),
+                None => return Err(Error::UndefinedVariable(local.name.to_string())),
+            },
+            Op::SetLocal(local) => {
+                let value = self.pop();
+                self.frame().set(local.depth, local.slot, value);
+                self.stack.push(Object::Symbol(local.name));
+            }
+            Op::DefineGlobal(key) => {
+                let existing = self.interpreter.env.get_symbol(key).cloned();
+                let value = define(existing, self.pop());
+                self.interpreter.env.insert_symbol(key, value);
+                self.stack.push(Object::Symbol(key));
+            }
+            Op::DefineLocal(local) => {
+                let existing = self.frame().get(local.depth, local.slot);
+                let value = define(existing, self.pop());
+                self.frame().set(local.depth, local.slot, value);
+                self.stack.push(Object::Symbol(local.name));
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Negate => {
+                let value = self.pop();
+                self.stack.push(interpreter::eval_negate(&value)?);
+            }
+            Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Power
+            | Op::Equal
+            | Op::NotEqual
+            | Op::GreaterThan
+            | Op::LessThan => {
+                let right = self.pop();
+                let left = self.pop();
+                let atom = match op {
+                    Op::Add => Atom::Plus,
+                    Op::Subtract => Atom::Minus,
+                    Op::Multiply => Atom::Multiply,
+                    Op::Divide => Atom::Divide,
+                    Op::Power => Atom::Power,
+                    Op::Equal => Atom::Equal,
+                    Op::NotEqual => Atom::NotEqual,
+                    Op::GreaterThan => Atom::GreaterThan,
+                    _ => Atom::LessThan,
+                };
+                let result = self.interpreter.eval_operator(&atom, &left, &right)?;
+                self.stack.push(result);
+            }
+            Op::Jump(to) => self.call_frame().ip = to as usize,
+            Op::JumpIfFalse(to, statement) => {
+                if !interpreter::expect_bool(self.pop(), statement)? {
+                    self.call_frame().ip = to as usize;
+                }
+            }
+            Op::Array(n) => {
+                let items = self.pop_n(n);
+                let array = self.interpreter.allocate(Object::array(items))?;
+                self.stack.push(array);
+            }
+            Op::Interpolate(n) => {
+                let mut s = String::new();
+                for part in self.pop_n(n).iter() {
+                    self.interpreter.print_to(&mut s, part, false)?;
+                }
+                let s = self.interpreter.allocate(Object::String(s))?;
+                self.stack.push(s);
+            }
+            Op::Index => {
+                let index = self.pop();
+                let target = self.pop();
+                let item = interpreter::eval_index(self.interpreter, &target, &index)?;
+                self.stack.push(item);
+            }
+            Op::SetIndex => {
+                let index = self.pop();
+                let target = self.pop();
+                let value = self.pop();
+                interpreter::eval_set_index(self.interpreter, &target, &index, value.clone())?;
+                self.stack.push(value);
+            }
+            Op::GetField(field) => {
+                let target = self.pop();
+                self.stack.push(interpreter::eval_field(&target, field)?);
+            }
+            Op::SetField(field) => {
+                let target = self.pop();
+                let value = self.pop();
+                interpreter::eval_set_field(&target, field, value.clone())?;
+                self.stack.push(value);
+            }
+            Op::Call(n) | Op::TailCall(n) => {
+                let args = self.pop_n(n);
+                match self.pop() {
+                    //Scripted functions run in this loop rather than recursing
+                    function @ Object::Function(_) | function @ Object::Generic(_) => {
+                        let closure = self.interpreter.method(&function, &args)?;
+                        //Only function bodies have tail calls, so the frame being
+                        //replaced is always a call to a scripted function
+                        let base = if let Op::TailCall(_) = op {
+                            let replaced = self.calls.pop().expect("Tail calls are in functions");
+                            self.stack.truncate(replaced.base);
+                            self.interpreter.replace_call(closure.function.name);
+                            replaced.base
+                        } else {
+                            self.interpreter.enter(closure.function.name)?;
+                            self.stack.len()
+                        };
+                        let frame = interpreter::new_frame(&closure, &args)?;
+                        let chunk = compiled(self.interpreter, &closure.function);
+                        self.calls.push(CallFrame {
+                            chunk,
+                            ip: 0,
+                            frame: Some(frame),
+                            base,
+                        });
+                    }
+                    function => {
+                        let result = self.interpreter.call(&function, &args)?;
+                        self.stack.push(result);
+                    }
+                }
+            }
+            Op::Closure(idx) => {
+                let call = self.call_frame();
+                let closure = Closure {
+                    function: call.chunk.functions[idx as usize].clone(),
+                    frame: call.frame.clone(),
+                };
+                self.stack.push(Object::Function(closure));
+            }
+            Op::Try(to) | Op::Finally(to) => self.handlers.push(Handler {
+                calls: self.calls.len(),
+                stack: self.stack.len(),
+                ip: to as usize,
+                finally: op == Op::Finally(to),
+            }),
+            Op::EndTry => {
+                self.handlers.pop();
+            }
+            Op::Rethrow => {
+                let caught = self.pop();
+                return Err(self.interpreter.throw(caught));
+            }
+            Op::TypeError(idx) => {
+                let message = self.call_frame().chunk.constants[idx as usize].to_string();
+                return Err(Error::TypeError(message));
+            }
+            Op::Return => {
+                let value = self.pop();
+                let calls = self.calls.len();
+                if self.handlers.last().is_some_and(|h| h.calls == calls) {
+                    return Err(Error::Return(value));
+                }
+                return Ok(self.ret(value));
+            }
+        }
+        Ok(None)
+    }
+}
+
+//Runs a script with both backends and checks they agree, returning the tree walker's
+//result. The tree walker runs on the interpreter passed in so its state can be inspected.
+#[cfg(test)]
+pub fn eval_both(interpreter: &mut Interpreter, ast: &AST) -> Result<Object> {
+    use crate::enviroment::Enviroment;
+    use crate::interpreter::Backend;
+
+    let mut vm = Interpreter::new(Enviroment::new());
+    vm.set_backend(Backend::Bytecode);
+    let expected = interpreter.run(ast);
+    let result = vm.run(ast);
+    assert_eq!(
+        expected.as_ref().map(Object::repr),
+        result.as_ref().map(Object::repr),
+        "The tree walker and vm disagree on {:?}",
+        ast
+    );
+    expected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enviroment::Enviroment;
+    use crate::interpreter::Backend;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use indoc::indoc;
+
+    fn run(s: &str) -> Result<Object> {
+        let ast = Parser::new(Lexer::new(s)).parse_block().unwrap();
+        let mut interpreter = Interpreter::new(Enviroment::new());
+        interpreter.set_backend(Backend::Bytecode);
+        interpreter.set_max_depth(50_000);
+        interpreter.run(&ast)
+    }
+
+    #[test]
+    fn deep_recursion_runs_in_the_vm_loop() {
+        let s = indoc!(
+            "
+            function count(n)
+                if n > 0 then
+                    1 + count(n - 1)
+                else
+                    0
+                end
+            end
+            count(40000)
+        "
+        );
+        assert_eq!(run(s), Ok(Object::Float(40000.0)));
+    }
+
+    #[test]
+    fn errors_unwind_every_call() {
+        let s = indoc!(
+            "
+            function f(x)
+                x + missing
+            end
+            f(1)
+        "
+        );
+        assert_eq!(run(s), Err(Error::UndefinedVariable("missing".to_string())));
+    }
+}